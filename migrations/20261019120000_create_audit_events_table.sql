@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE audit_events(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    occurred_at timestamptz NOT NULL,
+    actor TEXT,
+    method TEXT NOT NULL,
+    route TEXT NOT NULL,
+    status SMALLINT NOT NULL,
+    payload TEXT
+);
+
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
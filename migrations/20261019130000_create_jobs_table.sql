@@ -0,0 +1,20 @@
+-- Add migration script here
+CREATE TABLE jobs(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    -- queued, running or dead
+    status TEXT NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at timestamptz NOT NULL,
+    -- When a running job's lease expires, and it's queued again
+    locked_until timestamptz,
+    last_error TEXT,
+    created_at timestamptz NOT NULL
+);
+
+CREATE INDEX jobs_queued_run_at_idx ON jobs (run_at) WHERE status = 'queued';
+CREATE INDEX jobs_running_locked_until_idx ON jobs (locked_until)
+    WHERE status = 'running';
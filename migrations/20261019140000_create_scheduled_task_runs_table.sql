@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE scheduled_task_runs(
+    name TEXT NOT NULL,
+    PRIMARY KEY (name),
+    last_scheduled_at timestamptz NOT NULL,
+    last_finished_at timestamptz NOT NULL,
+    last_error TEXT
+);
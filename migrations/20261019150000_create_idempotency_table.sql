@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE idempotency(
+    -- Username of the authenticated user, empty for anonymous requests
+    requester TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    -- Anonymous requests' keys are also scoped by the request
+    request_hash TEXT NOT NULL,
+    PRIMARY KEY (requester, idempotency_key, request_hash),
+    -- Unset while the request is in progress
+    response_status SMALLINT,
+    response_headers JSONB,
+    response_body BYTEA,
+    created_at timestamptz NOT NULL
+);
+-- Users' keys are reused for a different request if the hash differs
+CREATE UNIQUE INDEX idempotency_user_key
+    ON idempotency (requester, idempotency_key)
+    WHERE requester <> '';
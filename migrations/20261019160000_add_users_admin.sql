@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Admins may use the `/admin` endpoints
+ALTER TABLE users ADD COLUMN admin BOOLEAN NOT NULL DEFAULT false;
@@ -5,96 +5,522 @@ extern crate quote;
 extern crate core;
 
 use proc_macro::TokenStream;
-use proc_macro2::{Span, TokenStream as TokenStream2};
+use proc_macro2::TokenStream as TokenStream2;
 use proc_macro_error::{abort, proc_macro_error, SpanRange};
 use syn::punctuated::Punctuated;
 use syn::token::Comma;
-use syn::{Ident, ItemFn, Lit, Meta, MetaList, NestedMeta};
+use syn::{
+    parse_macro_input, AttributeArgs, Ident, ItemFn, Lit, Meta, MetaList,
+    MetaNameValue, NestedMeta,
+};
 
+/// Adds a `PATH` const and `url(...)` builder to an actix endpoint.
+///
+/// Accepts an optional `scope` argument for endpoints mounted inside a
+/// `web::scope`, so that `PATH` reflects the full URL.
+/// ```ignore
+/// #[add_path_const(scope = "/api")]
+/// #[get("/users/{id}")]
+/// pub async fn get_user(id: web::Path<u32>) -> HttpResponse { ... }
+///
+/// assert_eq!("/api/users/{id}", get_user::PATH);
+/// ```
+///
+/// Endpoints belonging to API versions list them, oldest first, with
+/// `versions(...)`. A `{VERSION}_PATH` const and `url_{version}` builder are
+/// added for each version, while `PATH` and `url` point at the newest one.
+/// ```ignore
+/// #[add_path_const(versions(v1, v2))]
+/// #[get("/users/{id}")]
+/// pub async fn get_user(id: web::Path<u32>) -> HttpResponse { ... }
+///
+/// assert_eq!("/v1/users/{id}", get_user::V1_PATH);
+/// assert_eq!("/v2/users/{id}", get_user::PATH);
+/// ```
 #[proc_macro_error]
 #[proc_macro_attribute]
 pub fn add_path_const(args: TokenStream, item: TokenStream) -> TokenStream {
     add_path_const_attr(args, item)
 }
 
-fn add_path_const_attr(_args: TokenStream, item: TokenStream) -> TokenStream {
-    let item_fn: ItemFn = syn::parse(item).unwrap_or_else(|_| {
+/// Submits the endpoint to the app's route registry so that it is mounted by
+/// `registry::configure`.
+///
+/// Expects a `crate::registry::Endpoint` type collected with
+/// `inventory::collect!`, and must be placed above the method attribute. Any
+/// scope or versions are taken from a sibling `#[add_path_const(...)]`, with
+/// versioned endpoints registered once per `crate::api_version::ApiVersion`.
+/// ```ignore
+/// #[register_endpoint]
+/// #[add_path_const]
+/// #[get("/health_check")]
+/// pub async fn health_check() -> impl Responder { ... }
+/// ```
+///
+/// Endpoints whose requests should be recorded in the audit log opt in with
+/// `#[register_endpoint(audit)]`, and endpoints which replay their response
+/// to requests repeating an `Idempotency-Key` opt in with
+/// `#[register_endpoint(idempotent)]`. Endpoints extracting a `UnitOfWork`
+/// opt in with `#[register_endpoint(transactional)]`. They can be combined,
+/// e.g. `#[register_endpoint(audit, idempotent)]`.
+#[proc_macro_error]
+#[proc_macro_attribute]
+pub fn register_endpoint(args: TokenStream, item: TokenStream) -> TokenStream {
+    register_endpoint_attr(args, item)
+}
+
+/// Actix attributes which route a single method, e.g. `#[get("/path")]`.
+const METHOD_ATTRS: [&str; 9] = [
+    "get", "post", "put", "delete", "head", "connect", "options", "trace",
+    "patch",
+];
+/// Actix attribute which routes multiple methods, e.g.
+/// `#[route("/path", method = "GET", method = "POST")]`
+const ROUTE_ATTR: &str = "route";
+
+/// The route described by an actix routing attribute.
+struct EndpointRoute {
+    /// Upper case HTTP methods, e.g. `GET`
+    methods: Vec<String>,
+    /// Path relative to the endpoint's scope
+    path: String,
+}
+
+/// Arguments accepted by `#[add_path_const(...)]`.
+#[derive(Default)]
+struct EndpointArgs {
+    /// e.g. `/api`
+    scope: Option<String>,
+    /// API versions, oldest first, e.g. `v1`
+    versions: Vec<Ident>,
+}
+
+fn add_path_const_attr(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let args = parse_endpoint_args(&args);
+    let item_fn = parse_item_fn(item);
+    let fn_ident = &item_fn.sig.ident;
+    let route = get_method_attr(&item_fn);
+    check_not_above_register_endpoint(&item_fn);
+
+    let impl_path = impl_path_for_struct(&args, &route.path, fn_ident);
+
+    quote!(
+        #item_fn
+
+        #impl_path
+    )
+    .into()
+}
+
+fn register_endpoint_attr(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let RegisterArgs {
+        audit,
+        idempotent,
+        transactional,
+    } = parse_register_args(&args);
+    let item_fn = parse_item_fn(item);
+    let fn_ident = &item_fn.sig.ident;
+    let route = get_method_attr(&item_fn);
+    let args = get_args_from_sibling_attr(&item_fn);
+    let scope = args.scope.unwrap_or_default();
+    let name = fn_ident.to_string();
+    let methods = &route.methods;
+
+    let submit_endpoint = |version: Option<&Ident>| {
+        let endpoint_path = versioned_path(version, &scope, &route.path);
+        let version = match version {
+            Some(version) => {
+                let variant = version_variant(version);
+                quote!(Some(crate::api_version::ApiVersion::#variant))
+            }
+            None => quote!(None),
+        };
+        quote!(::inventory::submit! {
+            crate::registry::Endpoint {
+                name: #name,
+                path: #endpoint_path,
+                scope: #scope,
+                version: #version,
+                methods: &[#(#methods),*],
+                audit: #audit,
+                idempotent: #idempotent,
+                transactional: #transactional,
+                register: |config| {
+                    config.service(#fn_ident);
+                },
+            }
+        })
+    };
+    let submissions: Vec<TokenStream2> = match args.versions.is_empty() {
+        true => vec![submit_endpoint(None)],
+        false => args
+            .versions
+            .iter()
+            .map(Some)
+            .map(submit_endpoint)
+            .collect(),
+    };
+
+    quote!(
+        #item_fn
+
+        #(#submissions)*
+    )
+    .into()
+}
+
+/// Options endpoints opt in to with `#[register_endpoint(...)]`.
+#[derive(Default)]
+struct RegisterArgs {
+    audit: bool,
+    idempotent: bool,
+    transactional: bool,
+}
+
+fn parse_register_args(args: &AttributeArgs) -> RegisterArgs {
+    let mut register_args = RegisterArgs::default();
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("audit") => {
+                register_args.audit = true;
+            }
+            NestedMeta::Meta(Meta::Path(path))
+                if path.is_ident("idempotent") =>
+            {
+                register_args.idempotent = true;
+            }
+            NestedMeta::Meta(Meta::Path(path))
+                if path.is_ident("transactional") =>
+            {
+                register_args.transactional = true;
+            }
+            _ => abort!(
+                arg,
+                "Unexpected argument.";
+                help = "The only supported arguments are `audit`, \
+                    `idempotent` and `transactional`, set the scope with \
+                    #[add_path_const(scope = \"/scope\")]"
+            ),
+        }
+    }
+    register_args
+}
+
+fn parse_item_fn(item: TokenStream) -> ItemFn {
+    syn::parse(item).unwrap_or_else(|_| {
         abort!(
             SpanRange::call_site(),
             "Endpoint attribute can only be applied to a function."
         )
-    });
+    })
+}
+
+/// Find the actix routing attribute, e.g. `#[get("/path")]` or
+/// `#[actix_web::route("/path", method = "GET")]`, and return its route.
+fn get_method_attr(item_fn: &ItemFn) -> EndpointRoute {
     let fn_ident = &item_fn.sig.ident;
-    let mut endpoint_path: Option<String> = None;
-    let methods: Vec<Ident> = vec!["get", "post", "put", "delete", "head"]
-        .into_iter()
-        .map(|x| Ident::new(x, Span::call_site()))
-        .collect();
-    let mut valid_method_attr_exists = false;
     for attr in item_fn.attrs.iter() {
-        let attr = attr.parse_meta().expect("Failed to parse attr");
+        let attr = match attr.parse_meta() {
+            Ok(attr) => attr,
+            Err(_) => continue,
+        };
         if let Meta::List(MetaList {
             ref path,
             ref nested,
             ..
         }) = attr
         {
-            if let Some(attr_ident) = path.get_ident() {
-                if methods.contains(attr_ident) {
-                    valid_method_attr_exists = true;
-                    endpoint_path = get_endpoint_path_from_attr_args(
-                        nested,
-                        &SpanRange::from_tokens(&attr),
-                    );
-                    break;
-                }
+            let attr_name = match path.segments.last() {
+                Some(segment) => segment.ident.to_string(),
+                None => continue,
+            };
+            let span_range = SpanRange::from_tokens(&attr);
+            if METHOD_ATTRS.contains(&attr_name.as_str()) {
+                return EndpointRoute {
+                    methods: vec![attr_name.to_uppercase()],
+                    path: get_endpoint_path_from_attr_args(nested, &span_range),
+                };
+            } else if attr_name == ROUTE_ATTR {
+                return EndpointRoute {
+                    methods: get_methods_from_route_args(nested, &span_range),
+                    path: get_endpoint_path_from_attr_args(nested, &span_range),
+                };
             }
         }
     }
 
-    if !valid_method_attr_exists {
-        abort!(
-            &SpanRange::from_tokens(&fn_ident),
-            "No valid method attribute exists for this function. \n\te.g. #[get(\"/path\")]"
-        )
-    }
-
-    let impl_path = impl_path_for_struct(endpoint_path, &fn_ident);
-
-    quote!(
-        #item_fn
-
-        #impl_path
+    abort!(
+        &SpanRange::from_tokens(&fn_ident),
+        "No valid method attribute exists for this function. \n\te.g. #[get(\"/path\")]"
     )
-    .into()
 }
 
 fn get_endpoint_path_from_attr_args(
     args: &Punctuated<NestedMeta, Comma>,
     span_range: &SpanRange,
-) -> Option<String> {
+) -> String {
     for arg in args.iter() {
         if let NestedMeta::Lit(Lit::Str(endpoint_path)) = arg {
-            return Some(endpoint_path.value());
+            return endpoint_path.value();
         }
     }
     abort!(span_range, "No endpoint path in method attribute.");
 }
 
+fn get_methods_from_route_args(
+    args: &Punctuated<NestedMeta, Comma>,
+    span_range: &SpanRange,
+) -> Vec<String> {
+    let methods: Vec<String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(method),
+                ..
+            })) if path.is_ident("method") => {
+                Some(method.value().to_uppercase())
+            }
+            _ => None,
+        })
+        .collect();
+
+    if methods.is_empty() {
+        abort!(
+            span_range,
+            "No method in route attribute. \n\te.g. #[route(\"/path\", method = \"GET\")]"
+        )
+    }
+    methods
+}
+
+/// Parse the optional `scope = "/prefix"` and `versions(...)` arguments.
+fn parse_endpoint_args(args: &[NestedMeta]) -> EndpointArgs {
+    let mut endpoint_args = EndpointArgs::default();
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(value),
+                ..
+            })) if path.is_ident("scope") => {
+                if endpoint_args.scope.is_some() {
+                    abort!(arg, "`scope` can only be set once.")
+                }
+                let value = value.value();
+                if !value.starts_with('/') {
+                    abort!(arg, "`scope` must start with a `/`.")
+                }
+                endpoint_args.scope = Some(value);
+            }
+            NestedMeta::Meta(Meta::List(MetaList { path, nested, .. }))
+                if path.is_ident("versions") =>
+            {
+                if !endpoint_args.versions.is_empty() {
+                    abort!(arg, "`versions` can only be set once.")
+                }
+                for version in nested {
+                    match version {
+                        NestedMeta::Meta(Meta::Path(path))
+                            if path.get_ident().is_some() =>
+                        {
+                            let ident = path.get_ident().unwrap().clone();
+                            if endpoint_args.versions.contains(&ident) {
+                                abort!(version, "Duplicate API version.")
+                            }
+                            endpoint_args.versions.push(ident);
+                        }
+                        _ => abort!(
+                            version,
+                            "Expected an API version.";
+                            help = "e.g. `versions(v1, v2)`"
+                        ),
+                    }
+                }
+                if endpoint_args.versions.is_empty() {
+                    abort!(arg, "`versions` must list at least one version.")
+                }
+            }
+            _ => abort!(
+                arg,
+                "Unexpected argument.";
+                help = "Supported arguments are `scope = \"/scope\"` and `versions(v1, v2)`"
+            ),
+        }
+    }
+    endpoint_args
+}
+
+/// `#[register_endpoint]` reads the scope and versions from
+/// `#[add_path_const(...)]`, which it can't do once the latter has expanded.
+fn check_not_above_register_endpoint(item_fn: &ItemFn) {
+    let register_attr = item_fn.attrs.iter().find(|attr| {
+        attr.path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "register_endpoint")
+    });
+    if let Some(attr) = register_attr {
+        abort!(
+            attr,
+            "#[register_endpoint] must be placed above #[add_path_const].";
+            help = "Otherwise the endpoint's scope and versions are ignored"
+        )
+    }
+}
+
+/// Find the arguments set on `#[add_path_const(...)]`, if there are any.
+fn get_args_from_sibling_attr(item_fn: &ItemFn) -> EndpointArgs {
+    item_fn
+        .attrs
+        .iter()
+        .filter(|attr| {
+            attr.path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "add_path_const")
+        })
+        .find_map(|attr| match attr.parse_meta() {
+            Ok(Meta::List(MetaList { nested, .. })) => Some(
+                parse_endpoint_args(&nested.into_iter().collect::<Vec<_>>()),
+            ),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Join the scope and path the same way actix does when mounting a resource
+/// inside a `web::scope`.
+fn scoped_path(scope: Option<&str>, path: &str) -> String {
+    match scope {
+        Some(scope) => format!("{}{path}", scope.trim_end_matches('/')),
+        None => path.to_string(),
+    }
+}
+
+/// Full path of an endpoint mounted within an API version's scope, e.g.
+/// `/v1/api/users`.
+fn versioned_path(version: Option<&Ident>, scope: &str, path: &str) -> String {
+    let path = scoped_path(Some(scope), path);
+    match version {
+        Some(version) => format!("/{version}{path}"),
+        None => path,
+    }
+}
+
+/// `v1` -> `V1`, matching the `ApiVersion` variant.
+fn version_variant(version: &Ident) -> Ident {
+    Ident::new(&version.to_string().to_uppercase(), version.span())
+}
+
 fn impl_path_for_struct(
-    endpoint_path: Option<String>,
+    args: &EndpointArgs,
+    path: &str,
     fn_ident: &Ident,
 ) -> TokenStream2 {
-    let endpoint_path = &*endpoint_path.unwrap_or_else(|| {
-        abort!(
-            SpanRange::call_site(),
-            "No endpoint path in method attribute."
+    let scope = args.scope.as_deref().unwrap_or_default();
+    let latest_version = args.versions.last();
+    let latest_path = versioned_path(latest_version, scope, path);
+    let latest_url = impl_url_fn(&format_ident!("url"), &latest_path);
+
+    let versioned_items = args.versions.iter().map(|version| {
+        let endpoint_path = versioned_path(Some(version), scope, path);
+        let path_const = format_ident!("{}_PATH", version_variant(version));
+        let url_fn =
+            impl_url_fn(&format_ident!("url_{}", version), &endpoint_path);
+        quote!(
+            pub const #path_const: &str = #endpoint_path;
+
+            #url_fn
         )
     });
 
     quote!(
         impl #fn_ident {
-            pub const PATH: &str = #endpoint_path;
+            pub const PATH: &str = #latest_path;
+
+            #latest_url
+
+            #(#versioned_items)*
         }
     )
 }
+
+fn impl_url_fn(url_fn: &Ident, endpoint_path: &str) -> TokenStream2 {
+    let (url_format, params) = parse_path_template(endpoint_path);
+
+    quote!(
+        /// Build the URL for this endpoint, percent-encoding each path
+        /// parameter.
+        pub fn #url_fn(
+            #(#params: impl ::std::fmt::Display),*
+        ) -> ::std::string::String {
+            format!(
+                #url_format,
+                #(::urlencoding::encode(&#params.to_string())),*
+            )
+        }
+    )
+}
+
+/// Split an actix path template into a format string and its parameters.
+///
+/// e.g. `/example_get/{email}` -> (`/example_get/{}`, [`email`])
+///
+/// Custom regexes such as `{id:\d+}` are stripped, only the name is kept.
+fn parse_path_template(endpoint_path: &str) -> (String, Vec<Ident>) {
+    let mut url_format = String::new();
+    let mut params: Vec<Ident> = vec![];
+    let mut chars = endpoint_path.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            url_format.push(c);
+            continue;
+        }
+
+        // Regexes may contain braces of their own, e.g. `{id:\d{3}}`
+        let mut depth = 1;
+        let mut param = String::new();
+        for c in chars.by_ref() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            param.push(c);
+        }
+        if depth != 0 {
+            abort!(
+                SpanRange::call_site(),
+                "Unclosed path parameter in endpoint path `{}`.",
+                endpoint_path
+            )
+        }
+
+        let name = param.split(':').next().unwrap_or_default().trim();
+        let ident = syn::parse_str::<Ident>(name).unwrap_or_else(|_| {
+            abort!(
+                SpanRange::call_site(),
+                "Path parameter `{}` is not a valid identifier.",
+                name
+            )
+        });
+        if params.contains(&ident) {
+            abort!(
+                SpanRange::call_site(),
+                "Path parameter `{}` is used more than once.",
+                name
+            )
+        }
+        params.push(ident);
+        url_format.push_str("{}");
+    }
+
+    (url_format, params)
+}
@@ -0,0 +1,9 @@
+use actix_web::HttpResponse;
+use proc_macros::add_path_const;
+
+#[add_path_const]
+pub async fn example_get() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn main() {}
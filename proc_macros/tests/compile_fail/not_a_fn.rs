@@ -0,0 +1,6 @@
+use proc_macros::add_path_const;
+
+#[add_path_const]
+pub struct ExampleGet;
+
+fn main() {}
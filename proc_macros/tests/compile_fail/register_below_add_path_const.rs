@@ -0,0 +1,11 @@
+use actix_web::{get, HttpResponse};
+use proc_macros::{add_path_const, register_endpoint};
+
+#[add_path_const(scope = "/api")]
+#[register_endpoint]
+#[get("/path")]
+pub async fn example_get() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use actix_web::{get, HttpResponse};
+use proc_macros::register_endpoint;
+
+#[register_endpoint(scope = "/api")]
+#[get("/path")]
+pub async fn example_get() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use actix_web::{route, HttpResponse};
+use proc_macros::add_path_const;
+
+#[add_path_const]
+#[route("/path")]
+pub async fn example_route() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use actix_web::{get, HttpResponse};
+use proc_macros::add_path_const;
+
+#[add_path_const(prefix = "/api")]
+#[get("/path")]
+pub async fn example_get() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn main() {}
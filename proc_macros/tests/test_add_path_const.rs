@@ -1,4 +1,4 @@
-use actix_web::{get, post, HttpResponse};
+use actix_web::{get, patch, post, route, HttpResponse};
 use proc_macros::add_path_const;
 
 #[test]
@@ -23,12 +23,89 @@ fn example_post_compiles() {
     assert_eq!("/path_post", example_post::PATH);
 }
 
-// TODO: trybuild tests to ensure error messages are correct
-// #[test]
-// #[should_panic]
-// fn does_not_compile_without_valid_method_attr() {
-//     #[add_path_const]
-//     pub async fn example_get() -> HttpResponse {
-//         HttpResponse::Ok().finish()
-//     }
-// }
+#[test]
+fn url_without_params_matches_path() {
+    #[add_path_const]
+    #[get("/path_get")]
+    pub async fn example_get() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    assert_eq!(example_get::PATH, example_get::url());
+}
+
+#[test]
+fn url_params_are_percent_encoded() {
+    #[add_path_const]
+    #[get("/users/{email}/posts/{id:\\d+}")]
+    pub async fn example_get() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    assert_eq!(
+        "/users/barry%40barry.com/posts/69",
+        example_get::url("barry@barry.com", 69)
+    );
+    assert_eq!(
+        "/users/%27%27%20OR%201%3D1%3B/posts/1",
+        example_get::url("'' OR 1=1;", 1)
+    );
+}
+
+#[test]
+fn all_method_attrs_compile() {
+    #[add_path_const]
+    #[patch("/path_patch")]
+    pub async fn example_patch() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[add_path_const]
+    #[actix_web::options("/path_options")]
+    pub async fn example_options() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[add_path_const]
+    #[route("/path_route", method = "GET", method = "POST")]
+    pub async fn example_route() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    assert_eq!("/path_patch", example_patch::PATH);
+    assert_eq!("/path_options", example_options::PATH);
+    assert_eq!("/path_route", example_route::PATH);
+}
+
+#[test]
+fn scope_is_prefixed_to_path() {
+    #[add_path_const(scope = "/api/")]
+    #[get("/users/{id}")]
+    pub async fn example_get() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    assert_eq!("/api/users/{id}", example_get::PATH);
+    assert_eq!("/api/users/1", example_get::url(1));
+}
+
+#[test]
+fn versioned_paths_are_generated() {
+    #[add_path_const(scope = "/api", versions(v1, v2))]
+    #[get("/users/{id}")]
+    pub async fn example_get() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    assert_eq!("/v1/api/users/{id}", example_get::V1_PATH);
+    assert_eq!("/v2/api/users/{id}", example_get::V2_PATH);
+    assert_eq!(example_get::V2_PATH, example_get::PATH);
+    assert_eq!("/v1/api/users/1", example_get::url_v1(1));
+    assert_eq!(example_get::url_v2(1), example_get::url(1));
+}
+
+#[test]
+fn misuse_does_not_compile() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/compile_fail/*.rs");
+}
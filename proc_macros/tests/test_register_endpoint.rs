@@ -0,0 +1,120 @@
+use actix_web::web::ServiceConfig;
+use actix_web::{get, post, route, HttpResponse};
+use proc_macros::{add_path_const, register_endpoint};
+
+mod api_version {
+    #[derive(Debug, PartialEq)]
+    pub enum ApiVersion {
+        V1,
+        V2,
+    }
+}
+
+mod registry {
+    use super::api_version::ApiVersion;
+    use super::ServiceConfig;
+
+    pub struct Endpoint {
+        pub name: &'static str,
+        pub path: &'static str,
+        pub scope: &'static str,
+        pub version: Option<ApiVersion>,
+        pub methods: &'static [&'static str],
+        pub audit: bool,
+        pub idempotent: bool,
+        pub transactional: bool,
+        pub register: fn(&mut ServiceConfig),
+    }
+
+    inventory::collect!(Endpoint);
+}
+
+#[register_endpoint]
+#[add_path_const]
+#[get("/registered_get")]
+pub async fn registered_get() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[register_endpoint(audit, idempotent, transactional)]
+#[post("/registered_post")]
+pub async fn registered_post() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[register_endpoint]
+#[add_path_const(scope = "/scoped")]
+#[route("/registered_route", method = "PUT", method = "DELETE")]
+pub async fn registered_route() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[register_endpoint]
+#[add_path_const(versions(v1, v2))]
+#[get("/registered_versioned")]
+pub async fn registered_versioned() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn find_endpoint(name: &str) -> &'static registry::Endpoint {
+    inventory::iter::<registry::Endpoint>
+        .into_iter()
+        .find(|endpoint| endpoint.name == name)
+        .expect("Endpoint was not registered")
+}
+
+#[test]
+fn annotated_endpoints_are_registered() {
+    let get_endpoint = find_endpoint("registered_get");
+    assert_eq!(registered_get::PATH, get_endpoint.path);
+    assert_eq!(&["GET"], get_endpoint.methods);
+    assert!(!get_endpoint.audit);
+    assert!(!get_endpoint.idempotent);
+    assert!(!get_endpoint.transactional);
+
+    let post_endpoint = find_endpoint("registered_post");
+    assert_eq!("/registered_post", post_endpoint.path);
+    assert_eq!(&["POST"], post_endpoint.methods);
+    assert!(post_endpoint.audit);
+    assert!(post_endpoint.idempotent);
+    assert!(post_endpoint.transactional);
+
+    let route_endpoint = find_endpoint("registered_route");
+    assert_eq!(registered_route::PATH, route_endpoint.path);
+    assert_eq!("/scoped", route_endpoint.scope);
+    assert_eq!(&["PUT", "DELETE"], route_endpoint.methods);
+    assert_eq!(None, route_endpoint.version);
+}
+
+#[test]
+fn versioned_endpoints_are_registered_for_each_version() {
+    let mut versioned_endpoints: Vec<_> = inventory::iter::<registry::Endpoint>
+        .into_iter()
+        .filter(|endpoint| endpoint.name == "registered_versioned")
+        .map(|endpoint| (endpoint.version.as_ref(), endpoint.path))
+        .collect();
+    versioned_endpoints.sort_by_key(|(_, path)| *path);
+
+    assert_eq!(
+        vec![
+            (
+                Some(&api_version::ApiVersion::V1),
+                registered_versioned::V1_PATH
+            ),
+            (
+                Some(&api_version::ApiVersion::V2),
+                registered_versioned::V2_PATH
+            ),
+        ],
+        versioned_endpoints
+    );
+}
+
+#[test]
+fn registered_endpoints_can_be_mounted() {
+    let _app = actix_web::App::new().configure(|config| {
+        for endpoint in inventory::iter::<registry::Endpoint> {
+            (endpoint.register)(config);
+        }
+    });
+}
@@ -0,0 +1,192 @@
+use crate::configuration::ApiSettings;
+use crate::registry;
+use actix_web::dev::HttpServiceFactory;
+use actix_web::middleware::{Condition, DefaultHeaders};
+use actix_web::web;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+
+/// Versions of the API, oldest first.
+///
+/// Endpoints opt in with `#[add_path_const(versions(v1, v2))]` and are mounted
+/// under each version's scope, e.g. `/v1/example_post`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+    /// Version answering requests to unversioned paths, e.g. `/example_post`,
+    /// so clients from before the API was versioned keep working.
+    pub const UNVERSIONED: ApiVersion = ApiVersion::V1;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    /// Scope the version's endpoints are mounted under, e.g. `/v1`
+    pub fn scope(&self) -> String {
+        format!("/{}", self.as_str())
+    }
+}
+
+impl TryFrom<String> for ApiVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        ApiVersion::ALL
+            .into_iter()
+            .find(|version| version.as_str() == value.to_lowercase())
+            .ok_or_else(|| {
+                anyhow!(
+                    "{value} is not a supported API version. \
+                    Supported versions: {:?}",
+                    ApiVersion::ALL.map(|version| version.as_str())
+                )
+            })
+    }
+}
+
+impl From<ApiVersion> for String {
+    fn from(value: ApiVersion) -> Self {
+        value.as_str().into()
+    }
+}
+
+/// Deprecation schedule for an old API version.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct VersionDeprecation {
+    pub version: ApiVersion,
+    pub deprecated_at: DateTime<Utc>,
+    /// When the version will stop responding, if decided
+    pub sunset_at: Option<DateTime<Utc>>,
+}
+
+impl VersionDeprecation {
+    /// `Deprecation` (RFC 9745) and `Sunset` (RFC 8594) headers for responses
+    /// from the deprecated version.
+    pub fn headers(&self) -> DefaultHeaders {
+        deprecation_headers(self.deprecated_at, self.sunset_at)
+    }
+}
+
+/// Deprecation schedule for the unversioned paths, which are aliases of
+/// `ApiVersion::UNVERSIONED`'s while clients move to versioned paths.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct UnversionedDeprecation {
+    pub deprecated_at: DateTime<Utc>,
+    /// When the unversioned paths will stop responding, if decided
+    pub sunset_at: Option<DateTime<Utc>>,
+}
+
+impl UnversionedDeprecation {
+    /// `Deprecation` and `Sunset` headers for responses from unversioned
+    /// paths.
+    pub fn headers(&self) -> DefaultHeaders {
+        deprecation_headers(self.deprecated_at, self.sunset_at)
+    }
+}
+
+fn deprecation_headers(
+    deprecated_at: DateTime<Utc>,
+    sunset_at: Option<DateTime<Utc>>,
+) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add(("Deprecation", format!("@{}", deprecated_at.timestamp())));
+    match sunset_at {
+        Some(sunset_at) => headers.add((
+            "Sunset",
+            sunset_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        )),
+        None => headers,
+    }
+}
+
+/// Scope containing every endpoint registered for `version`, adding
+/// deprecation headers to responses if the version has been deprecated.
+pub fn scope(
+    version: ApiVersion,
+    api_settings: &ApiSettings,
+) -> impl HttpServiceFactory {
+    let deprecation = api_settings.deprecation(version);
+    let headers = deprecation
+        .map(VersionDeprecation::headers)
+        .unwrap_or_default();
+
+    web::scope(&version.scope())
+        .configure(|config| registry::configure_version(config, version))
+        .wrap(Condition::new(deprecation.is_some(), headers))
+}
+
+/// Scope mounting `ApiVersion::UNVERSIONED`'s endpoints at their unversioned
+/// paths, adding deprecation headers to responses if they've been deprecated.
+///
+/// Matches every path, so must be mounted after the app's other services.
+pub fn unversioned_scope(
+    api_settings: &ApiSettings,
+) -> impl HttpServiceFactory {
+    let deprecation = api_settings.unversioned.as_ref();
+    let headers = deprecation
+        .map(UnversionedDeprecation::headers)
+        .unwrap_or_default();
+
+    web::scope("")
+        .configure(|config| {
+            registry::configure_version(config, ApiVersion::UNVERSIONED)
+        })
+        .wrap(Condition::new(deprecation.is_some(), headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{App, HttpResponse};
+    use chrono::TimeZone;
+
+    #[test]
+    fn versions_parse_from_their_names() {
+        for version in ApiVersion::ALL {
+            assert_eq!(
+                version,
+                ApiVersion::try_from(version.as_str().to_string()).unwrap()
+            );
+        }
+        assert!(ApiVersion::try_from("v0".to_string()).is_err());
+    }
+
+    #[actix_web::test]
+    async fn deprecation_headers_are_formatted_correctly() {
+        let deprecation = VersionDeprecation {
+            version: ApiVersion::V1,
+            deprecated_at: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            sunset_at: Some(Utc.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap()),
+        };
+        let app = init_service(
+            App::new()
+                .wrap(deprecation.headers())
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let response =
+            call_service(&app, TestRequest::default().to_request()).await;
+
+        assert_eq!(
+            "@1672531200",
+            response.headers().get("Deprecation").unwrap()
+        );
+        assert_eq!(
+            "Sat, 01 Jul 2023 00:00:00 GMT",
+            response.headers().get("Sunset").unwrap()
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use crate::auth::AuthenticatedUser;
+use crate::clock::Clock;
+use crate::database::DbError;
+use crate::registry;
+use crate::telemetry::Redactor;
+use actix_web::dev::{
+    forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::error::PayloadError;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use futures::Stream;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Longer payload summaries are truncated
+const MAX_PAYLOAD_CHARS: usize = 1024;
+/// Summary of a body that couldn't be read
+pub const PAYLOAD_UNAVAILABLE: &str = "<payload unavailable>";
+/// Most events returned by a single query
+pub const MAX_QUERY_LIMIT: i64 = 1000;
+
+/// A request to an audited endpoint.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    /// User authenticated by the endpoint, if any
+    pub actor: Option<String>,
+    pub method: String,
+    /// Route template, i.e. the endpoint's `PATH`
+    pub route: String,
+    pub status: i16,
+    /// Redacted and truncated request body
+    pub payload: Option<String>,
+}
+
+/// Filters for `query`, newest events are returned first.
+#[derive(serde::Deserialize, Debug)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub route: Option<String>,
+    /// Defaults to, and is capped at, `MAX_QUERY_LIMIT`
+    pub limit: Option<i64>,
+}
+
+#[tracing::instrument(name = "Record audit event", skip(event, pool))]
+pub async fn record(
+    event: &AuditEvent,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events
+            (id, occurred_at, actor, method, route, status, payload)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        event.id,
+        event.occurred_at,
+        event.actor,
+        event.method,
+        event.route,
+        event.status,
+        event.payload,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert audit event")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Query audit events", skip(pool))]
+pub async fn query(
+    query: &AuditQuery,
+    pool: &PgPool,
+) -> Result<Vec<AuditEvent>, DbError> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_QUERY_LIMIT)
+        .clamp(0, MAX_QUERY_LIMIT);
+    let events = sqlx::query_as!(
+        AuditEvent,
+        r#"
+        SELECT id, occurred_at, actor, method, route, status, payload
+        FROM audit_events
+        WHERE ($1::TEXT IS NULL OR actor = $1)
+            AND ($2::TEXT IS NULL OR route = $2)
+        ORDER BY occurred_at DESC
+        LIMIT $3
+        "#,
+        query.actor,
+        query.route,
+        limit,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(events)
+}
+
+/// Middleware recording requests to endpoints registered with
+/// `#[register_endpoint(audit)]` in the `audit_events` table.
+///
+/// Failing to record an event is logged, but doesn't fail the request.
+pub struct AuditLog {
+    pool: PgPool,
+    redactor: Arc<Redactor>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AuditLog {
+    pub fn new(
+        pool: PgPool,
+        redactor: Arc<Redactor>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            pool,
+            redactor,
+            clock,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AuditLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AuditLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuditLogMiddleware {
+            service: Rc::new(service),
+            pool: self.pool.clone(),
+            redactor: self.redactor.clone(),
+            clock: self.clock.clone(),
+        }))
+    }
+}
+
+pub struct AuditLogMiddleware<S> {
+    service: Rc<S>,
+    pool: PgPool,
+    redactor: Arc<Redactor>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuditLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let method = request.method().to_string();
+        let route = match request.match_pattern() {
+            Some(route) if registry::is_audited(&method, &route) => route,
+            _ => return Box::pin(self.service.call(request)),
+        };
+        let service = self.service.clone();
+        let pool = self.pool.clone();
+        let redactor = self.redactor.clone();
+        let clock = self.clock.clone();
+
+        Box::pin(async move {
+            // The body is read here to be summarised, then put back for the
+            // endpoint to extract
+            let (payload, response) = match request.extract::<Bytes>().await {
+                Ok(body) => {
+                    let payload = summarise_payload(
+                        request.content_type(),
+                        &body,
+                        &redactor,
+                    );
+                    request.set_payload(bytes_to_payload(body));
+                    (payload, service.call(request).await)
+                }
+                // Still recorded, e.g. bodies over `max_payload_bytes`
+                Err(e) => (Some(PAYLOAD_UNAVAILABLE.into()), Err(e)),
+            };
+            // Endpoint errors are still `Ok` responses, only middleware errors
+            // lose the request
+            let (status, actor) = match &response {
+                Ok(response) => (
+                    response.status(),
+                    response
+                        .request()
+                        .extensions()
+                        .get::<AuthenticatedUser>()
+                        .map(|user| user.0.clone()),
+                ),
+                Err(e) => (e.as_response_error().status_code(), None),
+            };
+
+            let event = AuditEvent {
+                id: Uuid::new_v4(),
+                occurred_at: clock.now(),
+                actor,
+                method,
+                route,
+                status: status.as_u16() as i16,
+                payload,
+            };
+            if let Err(e) = record(&event, &pool).await {
+                tracing::error!(error = ?e, "Failed to record audit event");
+            }
+            response
+        })
+    }
+}
+
+/// Replaces a request's payload after it's been extracted, so it can be
+/// extracted again.
+pub(crate) fn bytes_to_payload(body: Bytes) -> Payload {
+    let stream: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+        Box::pin(futures::stream::once(ready(Ok(body))));
+    Payload::from(stream)
+}
+
+/// JSON and form bodies are redacted as JSON objects, other bodies are only
+/// summarised by their size.
+fn summarise_payload(
+    content_type: &str,
+    body: &[u8],
+    redactor: &Redactor,
+) -> Option<String> {
+    if body.is_empty() {
+        return None;
+    }
+    let value = match content_type {
+        "application/json" => serde_json::from_slice::<Value>(body).ok(),
+        "application/x-www-form-urlencoded" => {
+            serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+                .ok()
+                .map(|pairs| {
+                    Value::Object(
+                        pairs
+                            .into_iter()
+                            .map(|(key, value)| (key, Value::String(value)))
+                            .collect(),
+                    )
+                })
+        }
+        _ => None,
+    };
+    let summary = match value {
+        Some(value) => redactor.redact(&value.to_string()).into_owned(),
+        None => format!("<{} bytes>", body.len()),
+    };
+    Some(summary.chars().take(MAX_PAYLOAD_CHARS).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::RedactionSettings;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactionSettings::default())
+    }
+
+    #[test]
+    fn form_payloads_are_redacted() {
+        let body = b"username=barry&password=hunter2";
+
+        let summary = summarise_payload(
+            "application/x-www-form-urlencoded",
+            body,
+            &redactor(),
+        )
+        .unwrap();
+
+        assert!(summary.contains(r#""password":"[REDACTED]""#));
+        assert!(summary.contains(r#""username":"barry""#));
+    }
+
+    #[test]
+    fn other_payloads_are_summarised_by_size() {
+        let summary =
+            summarise_payload("text/plain", b"hunter2", &redactor()).unwrap();
+
+        assert_eq!("<7 bytes>", summary);
+        assert_eq!(None, summarise_payload("text/plain", b"", &redactor()));
+    }
+
+    #[test]
+    fn long_payloads_are_truncated() {
+        let body = format!(r#"{{"name":"{}"}}"#, "a".repeat(2000));
+
+        let summary =
+            summarise_payload("application/json", body.as_bytes(), &redactor())
+                .unwrap();
+
+        assert_eq!(MAX_PAYLOAD_CHARS, summary.chars().count());
+    }
+}
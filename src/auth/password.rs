@@ -1,7 +1,8 @@
 use crate::domain::Credentials;
+use crate::repository::UserRepository;
 use crate::routes::AuthError;
 use crate::telemetry::spawn_blocking_with_tracing;
-use actix_web::HttpRequest;
+use actix_web::{HttpMessage, HttpRequest};
 use anyhow::Context;
 use argon2::password_hash::SaltString;
 use argon2::{
@@ -9,39 +10,59 @@ use argon2::{
     Version,
 };
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
 use std::string::ToString;
 
-struct StoredCredentials {
-    pub stored_username: String,
-    pub stored_password_hash: Secret<String>,
-}
+/// Username of the user authenticated for a request, stored in the request's
+/// extensions so it can be recorded as the actor in the audit log.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(pub String);
 
-#[tracing::instrument(name = "Validate request", skip(request, pool))]
+#[tracing::instrument(name = "Validate request", skip(request, users))]
 pub async fn validate_request_auth(
     request: HttpRequest,
-    pool: &PgPool,
+    users: &dyn UserRepository,
 ) -> Result<(), AuthError> {
-    let credentials = Credentials::decode_from_basic_authentication_header(
-        request.headers(),
-    )?;
-    let _ = validate_credentials(credentials, pool).await?;
+    let credentials =
+        Credentials::decode_from_basic_authentication_header(request.headers())
+            .map_err(AuthError::InvalidCredentials)?;
+    let username = validate_credentials(credentials, users).await?;
+    request.extensions_mut().insert(AuthenticatedUser(username));
     Ok(())
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// Validate the request's 'Basic' credentials, failing with
+/// `AuthError::Forbidden` unless the user is an admin.
+#[tracing::instrument(name = "Validate admin request", skip(request, users))]
+pub async fn validate_admin_request_auth(
+    request: HttpRequest,
+    users: &dyn UserRepository,
+) -> Result<(), AuthError> {
+    validate_request_auth(request.clone(), users).await?;
+    let username = request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.0.clone())
+        .context("Authenticated user missing from request")?;
+    match users.is_admin(&username).await? {
+        true => Ok(()),
+        false => Err(AuthError::Forbidden(username)),
+    }
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, users))]
 pub async fn validate_credentials(
     credentials: Credentials,
-    pool: &PgPool,
+    users: &dyn UserRepository,
 ) -> Result<String, AuthError> {
     let mut username: Option<String> = None;
     let stored_hash: Secret<String>;
 
-    if let Some(stored_credentials) =
-        get_stored_credentials(credentials.username.as_ref(), pool).await?
+    if let Some(password_hash) = users
+        .get_password_hash(credentials.username.as_ref())
+        .await?
     {
-        username = Some(stored_credentials.stored_username);
-        stored_hash = stored_credentials.stored_password_hash;
+        username = Some(credentials.username.as_ref().to_string());
+        stored_hash = password_hash;
     } else {
         stored_hash =
             compute_password_hash(Secret::new("default_password".to_string()))?;
@@ -58,29 +79,6 @@ pub async fn validate_credentials(
         .map_err(AuthError::InvalidCredentials)
 }
 
-#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
-async fn get_stored_credentials(
-    username: &str,
-    pool: &PgPool,
-) -> Result<Option<StoredCredentials>, anyhow::Error> {
-    let credentials = sqlx::query!(
-        r#"
-        SELECT username, password
-        FROM users
-        WHERE username = $1
-        "#,
-        username
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to query database for credentials")?
-    .map(|row| StoredCredentials {
-        stored_username: username.to_string(),
-        stored_password_hash: Secret::new(row.password),
-    });
-    Ok(credentials)
-}
-
 #[tracing::instrument(
     name = "Verify hash",
     skip(expected_password_hash, received_password)
@@ -123,6 +121,8 @@ pub fn compute_password_hash(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::{Parseable, Password, Username};
+    use crate::repository::{InMemoryUserRepository, UserRepository};
     use claims::{assert_err, assert_ok};
 
     #[test]
@@ -139,6 +139,41 @@ mod tests {
         ));
     }
 
+    #[actix_web::test]
+    async fn credentials_are_validated_against_stored_hash() {
+        let users = InMemoryUserRepository::default();
+        let password = Secret::new("correct-horse".to_string());
+        users
+            .insert(
+                &Username::parse("barry".to_string()).unwrap(),
+                compute_password_hash(password).unwrap(),
+                false,
+            )
+            .await
+            .unwrap();
+        let credentials = |username: &str, password: &str| Credentials {
+            username: Username::parse(username.to_string()).unwrap(),
+            password: Password::parse(Secret::new(password.to_string()))
+                .unwrap(),
+        };
+
+        let valid =
+            validate_credentials(credentials("barry", "correct-horse"), &users)
+                .await;
+        let wrong_password = validate_credentials(
+            credentials("barry", "incorrect-horse"),
+            &users,
+        )
+        .await;
+        let unknown =
+            validate_credentials(credentials("bazza", "correct-horse"), &users)
+                .await;
+
+        assert_eq!("barry", valid.unwrap());
+        assert_err!(wrong_password);
+        assert_err!(unknown);
+    }
+
     #[test]
     fn correct_password_does_verify() {
         let password = Secret::new("password".to_string());
@@ -0,0 +1,206 @@
+use crate::auth::compute_password_hash;
+use crate::configuration::Settings;
+use crate::database::ConstraintKind;
+use crate::domain::{Parseable, Password, Username};
+use crate::migrations;
+use crate::registry;
+use crate::repository::UserRepository;
+use crate::telemetry::Redactor;
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+/// Runs the server, or one of the admin commands.
+#[derive(clap::Parser, Debug)]
+#[clap(about)]
+pub struct Cli {
+    /// Defaults to `serve`
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Run the server
+    Serve,
+    /// Apply pending migrations
+    Migrate {
+        #[clap(subcommand)]
+        command: Option<MigrateCommand>,
+    },
+    /// Add a user, reading their password from stdin
+    CreateUser {
+        username: String,
+        /// Allow the user to use the `/admin` endpoints
+        #[clap(long)]
+        admin: bool,
+    },
+    /// Replace a user's password, reading it from stdin
+    ResetPassword { username: String },
+    /// Check the configuration loads, and print it with secrets redacted
+    CheckConfig,
+    /// List the registered endpoints
+    Routes,
+}
+
+#[derive(clap::Subcommand, Debug, PartialEq, Eq)]
+pub enum MigrateCommand {
+    /// Apply pending migrations, the default
+    Run,
+    /// List applied and pending migrations, and any changed since they were
+    /// applied
+    Status,
+}
+
+/// Each migration's version, description and state, one per line, e.g.
+/// `20221220154800 create example post table (applied)`, see
+/// `migrations::MigrationReport`.
+pub async fn migrate(
+    pool: &PgPool,
+    command: Option<MigrateCommand>,
+) -> Result<String, anyhow::Error> {
+    if command.unwrap_or(MigrateCommand::Run) == MigrateCommand::Run {
+        migrations::run(pool).await?;
+    }
+    Ok(migrations::status(pool).await?.to_string())
+}
+
+pub async fn create_user(
+    users: &dyn UserRepository,
+    username: String,
+    password: Secret<String>,
+    admin: bool,
+) -> Result<(), anyhow::Error> {
+    let username = Username::parse(username).context("Invalid username")?;
+    let password_hash = hash_password(password)?;
+    users
+        .insert(&username, password_hash, admin)
+        .await
+        .map_err(|e| match e.kind() {
+            Some(ConstraintKind::Unique) => {
+                anyhow!("{username} already exists")
+            }
+            _ => anyhow::Error::new(e).context("Failed to insert user"),
+        })
+}
+
+pub async fn reset_password(
+    users: &dyn UserRepository,
+    username: String,
+    password: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    let username = Username::parse(username).context("Invalid username")?;
+    let password_hash = hash_password(password)?;
+    match users
+        .set_password_hash(&username, password_hash)
+        .await
+        .context("Failed to set password")?
+    {
+        true => Ok(()),
+        false => Err(anyhow!("{username} doesn't exist")),
+    }
+}
+
+/// Check the password meets the same rules as at login.
+fn hash_password(
+    password: Secret<String>,
+) -> Result<Secret<String>, anyhow::Error> {
+    let password = Password::parse(password).context("Invalid password")?;
+    Ok(compute_password_hash(password.as_ref().clone())?)
+}
+
+/// The settings as pretty-printed JSON, including defaults, with secrets and
+/// anything the log redaction settings would mask redacted.
+pub fn check_config(settings: &Settings) -> Result<String, anyhow::Error> {
+    let mut config = serde_json::to_value(settings)
+        .context("Failed to serialise settings")?;
+    Redactor::new(&settings.logging.redaction).redact_value(&mut config);
+    Ok(serde_json::to_string_pretty(&config)?)
+}
+
+/// Every registered endpoint's methods, path and handler, one per line and
+/// ordered by path, e.g. `GET /health_check health_check`.
+pub fn routes() -> String {
+    registry::endpoints()
+        .sorted_by_key(|endpoint| (endpoint.path, endpoint.methods))
+        .map(|endpoint| {
+            format!(
+                "{} {} {}",
+                endpoint.methods.join(","),
+                endpoint.path,
+                endpoint.name
+            )
+        })
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+    use clap::{CommandFactory, Parser};
+
+    #[test]
+    fn cli_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn subcommands_are_parsed() {
+        let parse = |args: &[&str]| Cli::parse_from(args).command;
+
+        assert_eq!(None, parse(&["app"]));
+        assert_eq!(
+            Some(Command::Migrate {
+                command: Some(MigrateCommand::Status)
+            }),
+            parse(&["app", "migrate", "status"])
+        );
+        assert_eq!(
+            Some(Command::CreateUser {
+                username: "barry".into(),
+                admin: false,
+            }),
+            parse(&["app", "create-user", "barry"])
+        );
+        assert_eq!(
+            Some(Command::CreateUser {
+                username: "barry".into(),
+                admin: true,
+            }),
+            parse(&["app", "create-user", "--admin", "barry"])
+        );
+    }
+
+    #[actix_web::test]
+    async fn users_are_created_and_their_passwords_reset() {
+        let users = InMemoryUserRepository::default();
+        let password = || Secret::new("correct-horse".to_string());
+
+        let reset_missing =
+            reset_password(&users, "barry".into(), password()).await;
+        create_user(&users, "barry".into(), password(), true)
+            .await
+            .unwrap();
+        let duplicate =
+            create_user(&users, "barry".into(), password(), false).await;
+        let reset = reset_password(&users, "barry".into(), password()).await;
+        let short =
+            reset_password(&users, "barry".into(), Secret::new("short".into()))
+                .await;
+
+        assert!(reset_missing.is_err());
+        assert!(users.is_admin("barry").await.unwrap());
+        assert!(duplicate.is_err());
+        assert!(reset.is_ok());
+        assert!(short.is_err());
+    }
+
+    #[test]
+    fn routes_are_listed() {
+        assert!(routes()
+            .lines()
+            .any(|route| route == "GET /health_check health_check"));
+    }
+}
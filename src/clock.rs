@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time, so it can be fixed in tests.
+///
+/// Use the `Data<dyn Clock>` app data rather than `Utc::now` in endpoints.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
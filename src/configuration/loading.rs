@@ -0,0 +1,649 @@
+use super::{AppSettings, DatabaseSettings, Settings};
+use once_cell::sync::Lazy;
+use secrecy::ExposeSecret;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fmt::{Debug, Display, Formatter};
+use std::path::Path;
+
+/// Shortest HMAC secret, as it's also the key cookies are signed with
+pub const MIN_HMAC_SECRET_BYTES: usize = 64;
+
+/// Every problem found while loading the settings, so they can all be fixed
+/// at once.
+#[derive(thiserror::Error)]
+pub struct SettingsError {
+    pub issues: Vec<SettingsIssue>,
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid settings:")?;
+        for issue in &self.issues {
+            write!(f, "\n  {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+// Lists the issues in `expect` panics, rather than their fields
+impl Debug for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl From<SettingsIssue> for SettingsError {
+    fn from(issue: SettingsIssue) -> Self {
+        Self {
+            issues: vec![issue],
+        }
+    }
+}
+
+/// A problem with a setting, or with a source of settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingsIssue {
+    /// Where the setting came from, e.g. `config/base.yml` or `DATABASE_URL`
+    pub source: String,
+    /// The setting's key, e.g. `database.pool.max_connections`, if the issue
+    /// is with a single setting
+    pub key: Option<String>,
+    pub kind: IssueKind,
+}
+
+impl SettingsIssue {
+    pub fn new(
+        source: impl Display,
+        key: Option<&str>,
+        kind: IssueKind,
+    ) -> Self {
+        Self {
+            source: source.to_string(),
+            key: key.map(str::to_string),
+            kind,
+        }
+    }
+}
+
+impl Display for SettingsIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{}: {key}: {}", self.source, self.kind),
+            None => write!(f, "{}: {}", self.source, self.kind),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    MissingFile,
+    /// The source couldn't be read or parsed
+    Unreadable(String),
+    /// A required section isn't set by any source
+    MissingSection,
+    /// A `$VARIABLE` in the value isn't set
+    MissingVariable(String),
+    InvalidValue(String),
+    /// Not a setting, e.g. because it's misspelt
+    UnknownKey,
+}
+
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueKind::MissingFile => write!(f, "not found"),
+            IssueKind::Unreadable(e) => write!(f, "{e}"),
+            IssueKind::MissingSection => write!(f, "missing"),
+            IssueKind::MissingVariable(name) => write!(f, "${name} isn't set"),
+            IssueKind::InvalidValue(message) => write!(f, "{message}"),
+            IssueKind::UnknownKey => write!(f, "unknown key"),
+        }
+    }
+}
+
+/// Settings from one source, as a tree of values.
+pub(super) struct Source {
+    pub name: String,
+    pub tree: Value,
+}
+
+/// Read the YAML file at `path`, expanding `$VARIABLES` in its values, or
+/// `None` if it doesn't exist.
+pub(super) fn read_file(
+    path: &Path,
+) -> Result<Option<Source>, Vec<SettingsIssue>> {
+    let name = path.display().to_string();
+    let unreadable = |e: &dyn Display| {
+        vec![SettingsIssue::new(
+            &name,
+            None,
+            IssueKind::Unreadable(e.to_string()),
+        )]
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(unreadable(&e)),
+    };
+    let mut tree =
+        to_tree(config::File::from_str(&text, config::FileFormat::Yaml))
+            .map_err(|e| unreadable(&e))?;
+
+    let mut issues = vec![];
+    expand_variables(&mut tree, "", &name, &mut issues);
+    match issues.is_empty() {
+        true => Ok(Some(Source { name, tree })),
+        false => Err(issues),
+    }
+}
+
+/// Settings from a config source, as a tree of values
+pub(super) fn to_tree(
+    source: impl config::Source + Send + Sync + 'static,
+) -> Result<Value, config::ConfigError> {
+    config::Config::builder()
+        .add_source(source)
+        .build()?
+        .try_deserialize()
+}
+
+/// Expand environment `$VARIABLES` in the tree's values, recording each one
+/// that isn't set.
+fn expand_variables(
+    value: &mut Value,
+    key: &str,
+    source: &str,
+    issues: &mut Vec<SettingsIssue>,
+) {
+    match value {
+        Value::String(text) => match shellexpand::env(text) {
+            Ok(expanded) => *text = expanded.into_owned(),
+            Err(e) => issues.push(SettingsIssue::new(
+                source,
+                Some(key),
+                IssueKind::MissingVariable(e.var_name),
+            )),
+        },
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                expand_variables(value, &join(key, field), source, issues);
+            }
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter_mut().enumerate() {
+                expand_variables(
+                    value,
+                    &format!("{key}[{index}]"),
+                    source,
+                    issues,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Values tried in place of an invalid required value, one of which most
+/// types accept
+static PLACEHOLDERS: Lazy<[Value; 6]> = Lazy::new(|| {
+    [
+        json!(0),
+        json!(""),
+        json!(false),
+        json!([]),
+        json!({}),
+        Value::Null,
+    ]
+});
+
+/// What's been found while checking the settings' sections.
+struct Checks {
+    /// The sources merged, highest precedence last
+    merged: Value,
+    /// Each checked section, serialised
+    known: Map<String, Value>,
+    issues: Vec<SettingsIssue>,
+}
+
+/// The sources of the settings, lowest precedence first.
+pub(super) struct Sources(pub Vec<Source>);
+
+impl Sources {
+    /// Deserialize and validate the settings, with every issue found if
+    /// they're invalid.
+    pub fn settings(&self) -> Result<Settings, SettingsError> {
+        let mut merged = Value::Object(Default::default());
+        for source in &self.0 {
+            merge(&mut merged, &source.tree);
+        }
+
+        let mut checks = Checks {
+            merged,
+            known: Map::new(),
+            issues: vec![],
+        };
+        let database = self.section(&mut checks, "database");
+        let app = self.section(&mut checks, "app");
+        let api = self.optional_section(&mut checks, "api");
+        let telemetry = self.optional_section(&mut checks, "telemetry");
+        let logging = self.optional_section(&mut checks, "logging");
+        let shutdown = self.optional_section(&mut checks, "shutdown");
+        let jobs = self.optional_section(&mut checks, "jobs");
+        let scheduler = self.optional_section(&mut checks, "scheduler");
+
+        // Sections are validated even if others are invalid
+        let invalid = database
+            .iter()
+            .flat_map(validate_database)
+            .chain(app.iter().flat_map(validate_app));
+        for (key, message) in invalid {
+            checks
+                .issues
+                .push(self.issue(&key, IssueKind::InvalidValue(message)));
+        }
+        // Every setting is serialised, so keys missing from the serialised
+        // settings aren't settings
+        let known = Value::Object(checks.known);
+        let mut issues = checks.issues;
+        for source in &self.0 {
+            unknown_keys(&source.tree, &known, "", &source.name, &mut issues);
+        }
+        match (
+            database, app, api, telemetry, logging, shutdown, jobs, scheduler,
+        ) {
+            (
+                Some(database),
+                Some(app),
+                Some(api),
+                Some(telemetry),
+                Some(logging),
+                Some(shutdown),
+                Some(jobs),
+                Some(scheduler),
+            ) if issues.is_empty() => Ok(Settings {
+                database,
+                app,
+                api,
+                telemetry,
+                logging,
+                shutdown,
+                jobs,
+                scheduler,
+                warnings: vec![],
+            }),
+            _ => Err(SettingsError { issues }),
+        }
+    }
+
+    /// The `key` section, or `None` if it's missing or invalid, recording why
+    /// in `checks`, along with its keys as known.
+    fn section<T: DeserializeOwned + Serialize>(
+        &self,
+        checks: &mut Checks,
+        key: &str,
+    ) -> Option<T> {
+        let section = match checks.merged.get(key) {
+            Some(section) => section.clone(),
+            None => {
+                checks
+                    .issues
+                    .push(self.issue(key, IssueKind::MissingSection));
+                return None;
+            }
+        };
+        let issues = checks.issues.len();
+        let value = self.deserialize_section::<T>(section, key, checks);
+        // The keys of sections which couldn't be checked aren't unknown
+        let known = value
+            .as_ref()
+            .and_then(|value| serde_json::to_value(value).ok())
+            .unwrap_or(Value::Null);
+        checks.known.insert(key.to_string(), known);
+        // Otherwise it may hold placeholders
+        value.filter(|_| checks.issues.len() == issues)
+    }
+
+    /// Each invalid value is recorded rather than only the first, by setting
+    /// it aside and deserialising the section again:
+    /// - invalid fields are removed, so optional ones fall back to defaults;
+    /// - required fields, reported missing once removed, and list elements,
+    ///   which can't be removed without shifting the elements after them, are
+    ///   replaced with each of `PLACEHOLDERS` in turn until one's accepted.
+    ///
+    /// Placeholders aren't real values, so the section's then only returned
+    /// for finding its keys.
+    fn deserialize_section<T: DeserializeOwned>(
+        &self,
+        mut section: Value,
+        key: &str,
+        checks: &mut Checks,
+    ) -> Option<T> {
+        let mut removed: Vec<String> = vec![];
+        // The value being replaced, and the placeholders not tried yet
+        let mut replacing: Option<(String, std::slice::Iter<Value>)> = None;
+        loop {
+            let (path, error) = match from_tree::<T>(&section) {
+                Ok(value) => return Some(value),
+                Err(e) => e,
+            };
+            let missing = missing_field(&error);
+            let message = match *error {
+                config::ConfigError::Type {
+                    unexpected,
+                    expected,
+                    ..
+                } => format!("expected {expected}, found {unexpected}"),
+                error => error.to_string(),
+            };
+            // The path of a missing field is the struct missing it
+            let field = match &missing {
+                Some(missing) => join(&path, missing),
+                None => path,
+            };
+            let mut placeholders = match replacing.take() {
+                // The last placeholder was rejected
+                Some((replaced, placeholders)) if replaced == field => {
+                    placeholders
+                }
+                // A removed field was required, and has already been recorded
+                _ if missing.is_some() && removed.contains(&field) => {
+                    PLACEHOLDERS.iter()
+                }
+                _ => {
+                    checks.issues.push(self.issue(
+                        &join(key, &field),
+                        IssueKind::InvalidValue(message),
+                    ));
+                    let element = matches!(
+                        segments(&field).last(),
+                        Some(Segment::Index(_))
+                    );
+                    match missing.is_none() && !element {
+                        true if remove(&mut section, &field) => {
+                            removed.push(field);
+                            continue;
+                        }
+                        // The whole section is invalid
+                        true => return None,
+                        false => PLACEHOLDERS.iter(),
+                    }
+                }
+            };
+            // Given up once every placeholder's been rejected
+            insert(&mut section, &field, placeholders.next()?.clone());
+            replacing = Some((field, placeholders));
+        }
+    }
+
+    /// The `key` section, or its default if no source sets it.
+    fn optional_section<T: DeserializeOwned + Serialize + Default>(
+        &self,
+        checks: &mut Checks,
+        key: &str,
+    ) -> Option<T> {
+        match checks.merged.get(key) {
+            Some(_) => self.section(checks, key),
+            None => {
+                let value = T::default();
+                let known = serde_json::to_value(&value).ok()?;
+                checks.known.insert(key.to_string(), known);
+                Some(value)
+            }
+        }
+    }
+
+    /// An issue with `key`, from the source with the highest precedence
+    /// setting it.
+    fn issue(&self, key: &str, kind: IssueKind) -> SettingsIssue {
+        let source = self
+            .0
+            .iter()
+            .rev()
+            .find(|source| lookup(&source.tree, key).is_some())
+            .or(self.0.first())
+            .map_or("settings", |source| source.name.as_str());
+        SettingsIssue::new(source, Some(key), kind)
+    }
+}
+
+const PORT_RANGE: &str = "must be between 1 and 65535";
+
+/// Semantic checks of a valid `app` section, as keys and messages.
+fn validate_app(app: &AppSettings) -> Vec<(String, String)> {
+    let mut invalid = vec![];
+    if app.hmac_secret.expose_secret().len() < MIN_HMAC_SECRET_BYTES {
+        invalid.push((
+            "app.hmac_secret".into(),
+            format!("must be at least {MIN_HMAC_SECRET_BYTES} bytes"),
+        ));
+    }
+    invalid
+}
+
+/// Semantic checks of a valid `database` section, as keys and messages.
+fn validate_database(database: &DatabaseSettings) -> Vec<(String, String)> {
+    let mut invalid = vec![];
+    if database.port == 0 {
+        invalid.push(("database.port".into(), PORT_RANGE.into()));
+    }
+    for (index, replica) in database.replication.replicas.iter().enumerate() {
+        if replica.port == Some(0) {
+            invalid.push((
+                format!("database.replication.replicas[{index}].port"),
+                PORT_RANGE.into(),
+            ));
+        }
+    }
+    let client_certs = [
+        ("database.ssl_client_cert", &database.ssl_client_cert),
+        ("database.ssl_client_key", &database.ssl_client_key),
+    ];
+    for (key, _) in client_certs.iter().filter(|(_, path)| path.is_some()) {
+        invalid.push((
+            key.to_string(),
+            "client certificates aren't supported until sqlx is upgraded to \
+             0.7"
+            .into(),
+        ));
+    }
+    if database.pool.max_connections == 0 {
+        invalid.push((
+            "database.pool.max_connections".into(),
+            "must be at least 1".into(),
+        ));
+    }
+    if database.pool.min_connections > database.pool.max_connections {
+        invalid.push((
+            "database.pool.min_connections".into(),
+            "must be at most max_connections".into(),
+        ));
+    }
+    invalid
+}
+
+/// Deserialize a tree of values with the config crate, which converts
+/// strings from environment variables to numbers and booleans where needed.
+///
+/// Fails with the key of the value which couldn't be deserialized, which is
+/// empty for the tree itself.
+fn from_tree<T: DeserializeOwned>(
+    tree: &Value,
+) -> Result<T, (String, Box<config::ConfigError>)> {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(
+            &tree.to_string(),
+            config::FileFormat::Json,
+        ))
+        .build()
+        .map_err(|e| (String::new(), Box::new(e)))?;
+    serde_path_to_error::deserialize(config).map_err(|e| {
+        let key = match e.path().iter().next() {
+            Some(_) => e.path().to_string(),
+            None => String::new(),
+        };
+        (key, Box::new(e.into_inner()))
+    })
+}
+
+/// The field named by serde's `missing_field` error, which is the only error
+/// a missing required value causes.
+fn missing_field(error: &config::ConfigError) -> Option<String> {
+    match error {
+        config::ConfigError::Message(message) => message
+            .strip_prefix("missing field `")
+            .and_then(|message| message.strip_suffix('`'))
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Merge `from` into `into`, `from`'s values taking precedence.
+fn merge(into: &mut Value, from: &Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (field, value) in from {
+                match into.get_mut(field) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        into.insert(field.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from.clone(),
+    }
+}
+
+/// Record the keys in `value` which aren't in `known`.
+fn unknown_keys(
+    value: &Value,
+    known: &Value,
+    key: &str,
+    source: &str,
+    issues: &mut Vec<SettingsIssue>,
+) {
+    match (value, known) {
+        (Value::Object(fields), Value::Object(known)) => {
+            for (field, value) in fields {
+                let key = join(key, field);
+                match known.get(field) {
+                    Some(known) => {
+                        unknown_keys(value, known, &key, source, issues)
+                    }
+                    None => issues.push(SettingsIssue::new(
+                        source,
+                        Some(&key),
+                        IssueKind::UnknownKey,
+                    )),
+                }
+            }
+        }
+        // Every element has the same keys
+        (Value::Array(values), Value::Array(known)) => {
+            if let Some(known) = known.first() {
+                for (index, value) in values.iter().enumerate() {
+                    let key = format!("{key}[{index}]");
+                    unknown_keys(value, known, &key, source, issues);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn join(key: &str, field: &str) -> String {
+    match key.is_empty() {
+        true => field.to_string(),
+        false => format!("{key}.{field}"),
+    }
+}
+
+enum Segment<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+/// Split a key such as `replicas[0].port` into its fields and indices.
+fn segments(key: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    for part in key.split('.') {
+        let mut pieces = part.split('[');
+        if let Some(field) = pieces.next().filter(|field| !field.is_empty()) {
+            segments.push(Segment::Field(field));
+        }
+        for index in pieces {
+            if let Ok(index) = index.trim_end_matches(']').parse() {
+                segments.push(Segment::Index(index));
+            }
+        }
+    }
+    segments
+}
+
+fn lookup<'a>(tree: &'a Value, key: &str) -> Option<&'a Value> {
+    segments(key)
+        .into_iter()
+        .try_fold(tree, |value, segment| match segment {
+            Segment::Field(field) => value.get(field),
+            Segment::Index(index) => value.get(index),
+        })
+}
+
+/// Set the value at `key`, whose parent must exist.
+fn insert(tree: &mut Value, key: &str, value: Value) {
+    let segments = segments(key);
+    let Some((last, parents)) = segments.split_last() else {
+        return;
+    };
+    let parent =
+        parents
+            .iter()
+            .try_fold(tree, |value, segment| match segment {
+                Segment::Field(field) => value.get_mut(*field),
+                Segment::Index(index) => value.get_mut(*index),
+            });
+    match (last, parent) {
+        (Segment::Field(field), Some(Value::Object(fields))) => {
+            fields.insert(field.to_string(), value);
+        }
+        (Segment::Index(index), Some(Value::Array(values)))
+            if *index < values.len() =>
+        {
+            values[*index] = value;
+        }
+        _ => {}
+    }
+}
+
+/// Remove the value at `key`, returning whether there was one.
+fn remove(tree: &mut Value, key: &str) -> bool {
+    let segments = segments(key);
+    let Some((last, parents)) = segments.split_last() else {
+        return false;
+    };
+    let mut value = tree;
+    for segment in parents {
+        let child = match segment {
+            Segment::Field(field) => value.get_mut(*field),
+            Segment::Index(index) => value.get_mut(*index),
+        };
+        match child {
+            Some(child) => value = child,
+            None => return false,
+        }
+    }
+    match (last, value) {
+        (Segment::Field(field), Value::Object(fields)) => {
+            fields.remove(*field).is_some()
+        }
+        (Segment::Index(index), Value::Array(values))
+            if *index < values.len() =>
+        {
+            values.remove(*index);
+            true
+        }
+        _ => false,
+    }
+}
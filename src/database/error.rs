@@ -0,0 +1,120 @@
+use sqlx::postgres::PgDatabaseError;
+
+/// Kinds of integrity constraint recognised in database errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstraintKind {
+    Unique,
+    ForeignKey,
+    NotNull,
+    Check,
+}
+
+impl ConstraintKind {
+    /// Recognise a Postgres `SQLSTATE` error code.
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "23505" => Some(Self::Unique),
+            "23503" => Some(Self::ForeignKey),
+            "23502" => Some(Self::NotNull),
+            "23514" => Some(Self::Check),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unique => "unique",
+            Self::ForeignKey => "foreign key",
+            Self::NotNull => "not-null",
+            Self::Check => "check",
+        }
+    }
+}
+
+/// A database error, with integrity constraint violations recognised so
+/// handlers can map them to their own typed errors.
+///
+/// ```
+/// # use actix_web_template::database::DbError;
+/// # enum PostError { Conflict(DbError), UnexpectedError(anyhow::Error) }
+/// # fn map(e: sqlx::Error) -> PostError {
+/// let e = DbError::from(e);
+/// match e.constraint() == Some("example_email_key") {
+///     true => PostError::Conflict(e),
+///     false => PostError::UnexpectedError(e.into()),
+/// }
+/// # }
+/// ```
+#[derive(thiserror::Error, Debug)]
+pub enum DbError {
+    #[error("Violated {} constraint {constraint}", kind.as_str())]
+    ConstraintViolation {
+        kind: ConstraintKind,
+        /// Name of the violated constraint. Not-null violations have no
+        /// constraint, so are named after the column, e.g. `example.email`.
+        constraint: String,
+        /// Missing for violations reported by in-memory repositories
+        #[source]
+        source: Option<sqlx::Error>,
+    },
+    #[error(transparent)]
+    Other(sqlx::Error),
+}
+
+impl DbError {
+    /// A violation detected without the database, e.g. by an in-memory
+    /// repository.
+    pub fn constraint_violation(
+        kind: ConstraintKind,
+        constraint: impl Into<String>,
+    ) -> Self {
+        DbError::ConstraintViolation {
+            kind,
+            constraint: constraint.into(),
+            source: None,
+        }
+    }
+
+    /// Name of the violated constraint, if any
+    pub fn constraint(&self) -> Option<&str> {
+        match self {
+            DbError::ConstraintViolation { constraint, .. } => Some(constraint),
+            DbError::Other(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> Option<ConstraintKind> {
+        match self {
+            DbError::ConstraintViolation { kind, .. } => Some(*kind),
+            DbError::Other(_) => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for DbError {
+    fn from(e: sqlx::Error) -> Self {
+        let violation = e
+            .as_database_error()
+            .and_then(|db_error| db_error.try_downcast_ref::<PgDatabaseError>())
+            .and_then(|db_error| {
+                let kind = ConstraintKind::from_code(db_error.code())?;
+                let constraint = match kind {
+                    ConstraintKind::NotNull => format!(
+                        "{}.{}",
+                        db_error.table().unwrap_or_default(),
+                        db_error.column().unwrap_or_default()
+                    ),
+                    _ => db_error.constraint()?.to_string(),
+                };
+                Some((kind, constraint))
+            });
+        match violation {
+            Some((kind, constraint)) => DbError::ConstraintViolation {
+                kind,
+                constraint,
+                source: Some(e),
+            },
+            None => DbError::Other(e),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod error;
+mod replicas;
+mod transaction;
+
+pub use error::*;
+pub use replicas::*;
+pub use transaction::*;
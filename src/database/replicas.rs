@@ -0,0 +1,369 @@
+use crate::clock::Clock;
+use crate::shutdown::ShutdownHook;
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::Error;
+use anyhow::Context;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use sqlx::PgPool;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Cookie holding when the session last wrote, in milliseconds since the epoch
+pub const LAST_WRITE_COOKIE: &str = "last_write";
+
+tokio::task_local! {
+    /// Set by `ReadYourWrites` for requests from sessions which wrote
+    /// recently
+    static READ_FROM_PRIMARY: bool;
+}
+
+/// The primary database pool, for writes, and replica pools, for read-only
+/// queries.
+///
+/// Reads are spread across the healthy replicas, and go to the primary if
+/// none are healthy or, within the `ReadYourWrites` middleware, if the
+/// session wrote recently. Reads run with `read` fail over to the primary if
+/// a replica can't be reached between health checks.
+#[derive(Clone)]
+pub struct DbPools {
+    primary: PgPool,
+    replicas: Arc<[Replica]>,
+    next: Arc<AtomicUsize>,
+}
+
+struct Replica {
+    pool: PgPool,
+    /// Unhealthy until the first health check passes
+    healthy: AtomicBool,
+}
+
+impl DbPools {
+    pub fn new(primary: PgPool, replicas: Vec<PgPool>) -> Self {
+        Self {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|pool| Replica {
+                    pool,
+                    healthy: AtomicBool::new(false),
+                })
+                .collect(),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// The pool for writes, and reads which must see them
+    pub fn primary(&self) -> &PgPool {
+        &self.primary
+    }
+
+    /// The pool for read-only queries, which may lag behind the primary
+    pub fn reader(&self) -> &PgPool {
+        self.replica()
+            .map_or(&self.primary, |(_, replica)| &replica.pool)
+    }
+
+    /// Run the read-only `query` on the pool from `reader`. If it's a replica
+    /// which can't be reached, the replica is marked unhealthy until the next
+    /// health check and the query is run again on the primary.
+    pub async fn read<T, F, Fut>(&self, query: F) -> Result<T, sqlx::Error>
+    where
+        F: Fn(PgPool) -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let (index, replica) = match self.replica() {
+            Some(replica) => replica,
+            None => return query(self.primary.clone()).await,
+        };
+        match query(replica.pool.clone()).await {
+            Err(e) if is_connection_error(&e) => {
+                tracing::warn!(
+                    error = ?e,
+                    replica = index,
+                    "Replica is unreachable, reading from the primary"
+                );
+                replica.healthy.store(false, Ordering::Relaxed);
+                query(self.primary.clone()).await
+            }
+            result => result,
+        }
+    }
+
+    /// The next healthy replica and its index, unless reads should go to the
+    /// primary.
+    fn replica(&self) -> Option<(usize, &Replica)> {
+        if READ_FROM_PRIMARY
+            .try_with(|primary| *primary)
+            .unwrap_or(false)
+        {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.replicas.len())
+            .map(|i| (start + i) % self.replicas.len())
+            .map(|index| (index, &self.replicas[index]))
+            .find(|(_, replica)| replica.healthy.load(Ordering::Relaxed))
+    }
+
+    /// Check each replica can be queried, marking those which can't as
+    /// unhealthy until the next check.
+    pub async fn check_replicas(&self) {
+        for (index, replica) in self.replicas.iter().enumerate() {
+            let result = sqlx::query("SELECT 1").execute(&replica.pool).await;
+            let healthy = result.is_ok();
+            if let Err(e) = result {
+                tracing::warn!(
+                    error = ?e,
+                    replica = index,
+                    "Replica failed health check, reading from the primary"
+                );
+            }
+            let was_healthy = replica.healthy.swap(healthy, Ordering::Relaxed);
+            if healthy && !was_healthy {
+                tracing::info!(replica = index, "Replica is healthy");
+            }
+        }
+    }
+}
+
+/// Errors connecting to, or acquiring a connection from, a pool, rather than
+/// errors from the query itself.
+fn is_connection_error(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // Connection exceptions, and the server shutting down or starting up
+        sqlx::Error::Database(e) => e.code().is_some_and(|code| {
+            code.starts_with("08") || ["57P01", "57P03"].contains(&&*code)
+        }),
+        _ => false,
+    }
+}
+
+/// Checks the replicas' health on an interval, until it's shut down.
+#[derive(Clone)]
+pub struct ReplicaHealthChecks {
+    pools: DbPools,
+    interval: Duration,
+    stop: Arc<watch::Sender<bool>>,
+    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl ReplicaHealthChecks {
+    pub fn new(pools: DbPools, interval: Duration) -> Self {
+        Self {
+            pools,
+            interval,
+            stop: Arc::new(watch::channel(false).0),
+            task: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Spawn the checks, the first running straight away. Does nothing if
+    /// there are no replicas.
+    pub fn spawn(&self) {
+        if self.pools.replicas.is_empty() {
+            return;
+        }
+        let checks = self.clone();
+        let mut stop = self.stop.subscribe();
+        let task = tokio::spawn(async move {
+            while !*stop.borrow() {
+                checks.pools.check_replicas().await;
+                tokio::select! {
+                    _ = tokio::time::sleep(checks.interval) => {}
+                    _ = stop.changed() => {}
+                }
+            }
+        });
+        *self.task.lock().expect("Health checks lock poisoned") = Some(task);
+    }
+}
+
+/// Stops the checks and closes the replica pools.
+#[async_trait::async_trait]
+impl ShutdownHook for ReplicaHealthChecks {
+    fn name(&self) -> &'static str {
+        "replicas"
+    }
+
+    async fn shutdown(&self) -> Result<(), anyhow::Error> {
+        self.stop.send_replace(true);
+        let task = self
+            .task
+            .lock()
+            .expect("Health checks lock poisoned")
+            .take();
+        if let Some(task) = task {
+            task.await.context("Replica health checks failed")?;
+        }
+        for replica in self.pools.replicas.iter() {
+            replica.pool.close().await;
+        }
+        Ok(())
+    }
+}
+
+/// Middleware sending a session's reads to the primary for `window` after it
+/// writes, so it sees its own writes despite replication lag.
+///
+/// Successful requests with unsafe methods, e.g. `POST`, count as writes, and
+/// are recorded in the `last_write` cookie.
+pub struct ReadYourWrites {
+    clock: Arc<dyn Clock>,
+    window: Duration,
+}
+
+impl ReadYourWrites {
+    pub fn new(clock: Arc<dyn Clock>, window: Duration) -> Self {
+        Self { clock, window }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ReadYourWrites
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ReadYourWritesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ReadYourWritesMiddleware {
+            service: Rc::new(service),
+            clock: self.clock.clone(),
+            window: self.window,
+        }))
+    }
+}
+
+pub struct ReadYourWritesMiddleware<S> {
+    service: Rc<S>,
+    clock: Arc<dyn Clock>,
+    window: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for ReadYourWritesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let now = self.clock.now().timestamp_millis();
+        let window = self.window.as_millis() as i64;
+        let wrote_recently = request
+            .cookie(LAST_WRITE_COOKIE)
+            .and_then(|cookie| cookie.value().parse::<i64>().ok())
+            .is_some_and(|last_write| now - last_write < window);
+        let writes = !request.method().is_safe();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let mut response = READ_FROM_PRIMARY
+                .scope(wrote_recently, service.call(request))
+                .await?;
+            let status = response.status();
+            if writes && !status.is_client_error() && !status.is_server_error()
+            {
+                let cookie = Cookie::build(LAST_WRITE_COOKIE, now.to_string())
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .max_age(time::Duration::milliseconds(window))
+                    .finish();
+                response.response_mut().add_cookie(&cookie)?;
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn unreachable_pool() -> PgPool {
+        PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://postgres@127.0.0.1:1/replica")
+            .expect("Failed to build pool")
+    }
+
+    #[tokio::test]
+    async fn reads_fall_back_to_the_primary_without_healthy_replicas() {
+        let pools = DbPools::new(unreachable_pool(), vec![unreachable_pool()]);
+
+        assert!(std::ptr::eq(pools.primary(), pools.reader()));
+        pools.check_replicas().await;
+        assert!(std::ptr::eq(pools.primary(), pools.reader()));
+    }
+
+    #[tokio::test]
+    async fn reads_are_spread_across_healthy_replicas() {
+        let pools = DbPools::new(
+            unreachable_pool(),
+            vec![unreachable_pool(), unreachable_pool()],
+        );
+        for replica in pools.replicas.iter() {
+            replica.healthy.store(true, Ordering::Relaxed);
+        }
+
+        let first = pools.reader();
+        let second = pools.reader();
+
+        assert!(!std::ptr::eq(pools.primary(), first));
+        assert!(!std::ptr::eq(pools.primary(), second));
+        assert!(!std::ptr::eq(first, second));
+        READ_FROM_PRIMARY
+            .scope(true, async {
+                assert!(std::ptr::eq(pools.primary(), pools.reader()));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn unreachable_replicas_fail_over_to_the_primary() {
+        let pools = DbPools::new(unreachable_pool(), vec![unreachable_pool()]);
+        pools.replicas[0].healthy.store(true, Ordering::Relaxed);
+        pools.replicas[0].pool.close().await;
+        let reads = AtomicUsize::new(0);
+
+        let result = pools
+            .read(|pool| {
+                reads.fetch_add(1, Ordering::Relaxed);
+                async move { sqlx::query("SELECT 1").execute(&pool).await }
+            })
+            .await;
+
+        // Both pools were tried, and the replica isn't read from again
+        assert!(result.is_err());
+        assert_eq!(2, reads.load(Ordering::Relaxed));
+        assert!(!pools.replicas[0].healthy.load(Ordering::Relaxed));
+        assert!(std::ptr::eq(pools.primary(), pools.reader()));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::audit::bytes_to_payload;
+use crate::configuration::{IsolationLevel, TransactionSettings};
+use crate::registry;
+use crate::routes::TransactionError;
+use actix_web::dev::{
+    forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::error::PayloadError;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use anyhow::{anyhow, Context};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use futures::Stream;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::cell::{Cell, RefCell};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::Poll;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// `SQLSTATE`s of serialization failures and deadlocks, after which the
+/// transaction can be retried
+const RETRYABLE_ERRORS: [&str; 2] = ["40001", "40P01"];
+
+/// Extractor for the request's transaction, begun from the pool the first
+/// time it's extracted.
+///
+/// The `Transactional` middleware commits it if the handler responds with a
+/// success or redirect, and rolls it back otherwise. Handlers return their
+/// own errors, so pass query results through `check` for serialization
+/// failures and deadlocks to retry the request. Only endpoints registered
+/// with `#[register_endpoint(transactional)]` can extract it.
+///
+/// ```no_run
+/// # use actix_web::HttpResponse;
+/// # use actix_web_template::database::UnitOfWork;
+/// async fn handler(
+///     unit_of_work: UnitOfWork,
+/// ) -> Result<HttpResponse, actix_web::Error> {
+///     let mut transaction = unit_of_work.transaction().await;
+///     let result = sqlx::query("DELETE FROM example")
+///         .execute(&mut *transaction)
+///         .await;
+///     unit_of_work
+///         .check(result)
+///         .map_err(actix_web::error::ErrorInternalServerError)?;
+///     Ok(HttpResponse::Ok().finish())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct UnitOfWork(Rc<UnitOfWorkState>);
+
+struct UnitOfWorkState {
+    transaction: Mutex<Transaction<'static, Postgres>>,
+    serialization_failed: Cell<bool>,
+}
+
+impl UnitOfWork {
+    /// The transaction, for running queries in
+    pub async fn transaction(
+        &self,
+    ) -> MutexGuard<'_, Transaction<'static, Postgres>> {
+        self.0.transaction.lock().await
+    }
+
+    /// Record a serialization failure or deadlock in `result`, so the request
+    /// is retried instead of failing.
+    pub fn check<T>(
+        &self,
+        result: Result<T, sqlx::Error>,
+    ) -> Result<T, sqlx::Error> {
+        if let Err(e) = &result {
+            if is_retryable(e) {
+                self.0.serialization_failed.set(true);
+            }
+        }
+        result
+    }
+}
+
+impl FromRequest for UnitOfWork {
+    type Error = TransactionError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let scope = request.extensions().get::<TransactionScope>().cloned();
+        Box::pin(async move {
+            let scope = scope.ok_or_else(|| {
+                anyhow!(
+                    "The endpoint isn't run in the Transactional middleware"
+                )
+            })?;
+            scope.unit_of_work().await
+        })
+    }
+}
+
+/// Where a request's unit of work is kept for the middleware, once it's begun.
+#[derive(Clone)]
+struct TransactionScope {
+    pool: PgPool,
+    isolation_level: IsolationLevel,
+    unit_of_work: Rc<RefCell<Option<UnitOfWork>>>,
+}
+
+impl TransactionScope {
+    async fn unit_of_work(&self) -> Result<UnitOfWork, TransactionError> {
+        if let Some(unit_of_work) = self.unit_of_work.borrow().as_ref() {
+            return Ok(unit_of_work.clone());
+        }
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to begin transaction")?;
+        if self.isolation_level != IsolationLevel::default() {
+            sqlx::query(&format!(
+                "SET TRANSACTION ISOLATION LEVEL {}",
+                self.isolation_level.as_sql()
+            ))
+            .execute(&mut transaction)
+            .await
+            .context("Failed to set transaction isolation level")?;
+        }
+        let unit_of_work = UnitOfWork(Rc::new(UnitOfWorkState {
+            transaction: Mutex::new(transaction),
+            serialization_failed: Cell::new(false),
+        }));
+        *self.unit_of_work.borrow_mut() = Some(unit_of_work.clone());
+        Ok(unit_of_work)
+    }
+
+    /// Commit or roll back the unit of work, if the request began one,
+    /// returning whether the request should be retried.
+    async fn finish(&self, succeeded: bool) -> Result<bool, TransactionError> {
+        let unit_of_work = match self.unit_of_work.borrow_mut().take() {
+            Some(unit_of_work) => unit_of_work,
+            None => return Ok(false),
+        };
+        let state = Rc::try_unwrap(unit_of_work.0)
+            .map_err(|_| anyhow!("The unit of work outlived its request"))?;
+        let transaction = state.transaction.into_inner();
+        if state.serialization_failed.get() {
+            return Ok(true);
+        }
+        if !succeeded {
+            transaction
+                .rollback()
+                .await
+                .context("Failed to roll back transaction")?;
+            return Ok(false);
+        }
+        match transaction.commit().await {
+            Ok(()) => Ok(false),
+            Err(e) if is_retryable(&e) => Ok(true),
+            Err(e) => Err(anyhow::Error::new(e)
+                .context("Failed to commit transaction")
+                .into()),
+        }
+    }
+}
+
+fn is_retryable(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| RETRYABLE_ERRORS.contains(&code.as_ref()))
+}
+
+/// Middleware committing or rolling back the `UnitOfWork` of requests to
+/// endpoints registered with `#[register_endpoint(transactional)]`, and
+/// retrying requests which fail with serialization failures or deadlocks, up
+/// to `TransactionSettings::max_retries` times.
+///
+/// Their request body is kept as it's read, so it can be replayed to retries.
+/// Other requests are passed straight through.
+pub struct Transactional {
+    pool: PgPool,
+    settings: TransactionSettings,
+    is_transactional: fn(&str, &str) -> bool,
+}
+
+impl Transactional {
+    pub fn new(pool: PgPool, settings: TransactionSettings) -> Self {
+        Self {
+            pool,
+            settings,
+            is_transactional: registry::is_transactional,
+        }
+    }
+
+    /// Choose the requests run in a `UnitOfWork` by their method and route
+    /// template, rather than from the registry, e.g. for apps which don't
+    /// use `registry::configure`.
+    pub fn routes(mut self, is_transactional: fn(&str, &str) -> bool) -> Self {
+        self.is_transactional = is_transactional;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Transactional
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TransactionalMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TransactionalMiddleware {
+            service: Rc::new(service),
+            pool: self.pool.clone(),
+            settings: self.settings.clone(),
+            is_transactional: self.is_transactional,
+        }))
+    }
+}
+
+pub struct TransactionalMiddleware<S> {
+    service: Rc<S>,
+    pool: PgPool,
+    settings: TransactionSettings,
+    is_transactional: fn(&str, &str) -> bool,
+}
+
+impl<S, B> Service<ServiceRequest> for TransactionalMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let method = request.method().to_string();
+        match request.match_pattern() {
+            Some(route) if (self.is_transactional)(&method, &route) => {}
+            _ => return Box::pin(self.service.call(request)),
+        }
+        let service = self.service.clone();
+        let scope = TransactionScope {
+            pool: self.pool.clone(),
+            isolation_level: self.settings.isolation_level,
+            unit_of_work: Rc::new(RefCell::new(None)),
+        };
+        let max_retries = self.settings.max_retries;
+        let body = Rc::new(RefCell::new(BytesMut::new()));
+        let payload: Pin<Box<dyn Stream<Item = _>>> =
+            Box::pin(RecordedPayload {
+                payload: request.take_payload(),
+                body: body.clone(),
+            });
+        request.set_payload(Payload::from(payload));
+
+        Box::pin(async move {
+            let mut retries = 0;
+            loop {
+                request.extensions_mut().insert(scope.clone());
+                let response = service.call(request).await?;
+                let status = response.status();
+                let succeeded =
+                    !status.is_client_error() && !status.is_server_error();
+                if !scope.finish(succeeded).await? {
+                    return Ok(response);
+                }
+                if retries == max_retries {
+                    return Err(TransactionError::Conflict.into());
+                }
+                retries += 1;
+                tracing::warn!(
+                    retries,
+                    "Serialization failure or deadlock, retrying"
+                );
+                let (http_request, _) = response.into_parts();
+                request = ServiceRequest::from_parts(
+                    http_request,
+                    bytes_to_payload(body.borrow().clone().freeze()),
+                );
+                // Routed again from the start
+                request.match_info_mut().reset();
+            }
+        })
+    }
+}
+
+/// Copies the request body into `body` as the handler reads it.
+struct RecordedPayload {
+    payload: Payload,
+    body: Rc<RefCell<BytesMut>>,
+}
+
+impl Stream for RecordedPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.payload).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.body.borrow_mut().extend_from_slice(chunk);
+        }
+        poll
+    }
+}
@@ -0,0 +1,37 @@
+use crate::domain::Email;
+use crate::telemetry::hash_sensitive;
+
+/// Sends emails, so delivery can be replaced in tests.
+///
+/// Use the `Data<dyn EmailSender>` app data in endpoints.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), anyhow::Error>;
+}
+
+/// Logs emails instead of delivering them, used until an email provider is
+/// configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogEmailSender;
+
+#[async_trait::async_trait]
+impl EmailSender for LogEmailSender {
+    async fn send(
+        &self,
+        recipient: &Email,
+        subject: &str,
+        _body: &str,
+    ) -> Result<(), anyhow::Error> {
+        tracing::info!(
+            recipient = hash_sensitive(recipient),
+            subject,
+            "Email not delivered, no email provider is configured"
+        );
+        Ok(())
+    }
+}
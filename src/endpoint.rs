@@ -1,36 +1,54 @@
-use crate::auth::validate_request_auth;
-use crate::routes::{AuthError, LoginError, PostError};
-use crate::{init_request_trace, routes};
+use crate::audit::AuditQuery;
+use crate::auth::{validate_admin_request_auth, validate_request_auth};
+use crate::readiness::ReadinessChecks;
+use crate::repository::{ExampleRepository, UserRepository};
+use crate::routes::{
+    AuditError, AuthError, LogFilterError, LoginError, PostError,
+};
+use crate::telemetry::LogFilter;
+use crate::{init_request_trace, init_sensitive_request_trace, routes};
 use actix_web::error::InternalError;
 use actix_web::http::header::LOCATION;
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
-use proc_macros::add_path_const;
+use proc_macros::{add_path_const, register_endpoint};
 use sqlx::PgPool;
 
 /// Get the data associated with an email address, or return 400
-#[add_path_const]
+#[register_endpoint]
+#[add_path_const(versions(v1, v2))]
 #[get("/example_get/{email}")]
 pub async fn example_get(
     email: web::Path<String>,
-    pool: web::Data<PgPool>,
+    examples: web::Data<dyn ExampleRepository>,
 ) -> impl Responder {
-    init_request_trace!("Processing new GET request", %email);
-    routes::example_get(email, pool).await
+    init_sensitive_request_trace!(
+        "Processing new GET request",
+        [email = email]
+    );
+    routes::example_get(email, examples).await
 }
 
 /// Add a new entry to database via urlencoded web form
-#[add_path_const]
+///
+/// Retries with the same `Idempotency-Key` header get the original response.
+#[register_endpoint(audit, idempotent)]
+#[add_path_const(versions(v1, v2))]
 #[post("/example_post")]
 pub async fn example_post(
     form: web::Form<routes::PostExampleForm>,
-    pool: web::Data<PgPool>,
+    examples: web::Data<dyn ExampleRepository>,
 ) -> Result<HttpResponse, PostError> {
-    init_request_trace!("Processing new POST request", %form.name, %form.email);
-    routes::example_post(form, pool).await
+    init_sensitive_request_trace!(
+        "Processing new POST request",
+        [email = form.email],
+        %form.name
+    );
+    routes::example_post(form, examples).await
 }
 
 /// Response 200 if server is running
+#[register_endpoint]
 #[add_path_const]
 #[get("/health_check")]
 pub async fn health_check() -> impl Responder {
@@ -38,18 +56,85 @@ pub async fn health_check() -> impl Responder {
     routes::health_check().await
 }
 
-/// Response 200 if 'Basic' authorisation credentials are valid
+/// Response 200 if the server's dependencies are available, otherwise 503
+#[register_endpoint]
 #[add_path_const]
+#[get("/ready")]
+pub async fn ready(checks: web::Data<ReadinessChecks>) -> HttpResponse {
+    init_request_trace!("Readiness check");
+    routes::ready(checks).await
+}
+
+/// Prometheus metrics for requests and the database
+#[register_endpoint]
+#[add_path_const]
+#[get("/metrics")]
+pub async fn metrics(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    routes::metrics(pool).await
+}
+
+/// Response 200 if 'Basic' authorisation credentials are valid
+#[register_endpoint]
+#[add_path_const(versions(v1, v2))]
 #[get("/example_auth")]
 pub async fn example_auth(
     request: HttpRequest,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
 ) -> Result<HttpResponse, AuthError> {
     init_request_trace!("Validate Basic credentials");
-    validate_request_auth(request, &pool).await?;
+    validate_request_auth(request, users.get_ref()).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
+/// The active log filter directive, requires an admin's 'Basic' authorisation
+#[register_endpoint]
+#[add_path_const(scope = "/admin")]
+#[get("/log_filter")]
+pub async fn get_log_filter(
+    request: HttpRequest,
+    users: web::Data<dyn UserRepository>,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, LogFilterError> {
+    init_request_trace!("Get log filter");
+    validate_admin_request_auth(request, users.get_ref()).await?;
+    routes::get_log_filter(log_filter).await
+}
+
+/// Replace the active log filter directive, optionally reverting it after a
+/// timeout. Requires an admin's 'Basic' authorisation
+#[register_endpoint(audit)]
+#[add_path_const(scope = "/admin")]
+#[put("/log_filter")]
+pub async fn set_log_filter(
+    request: HttpRequest,
+    body: web::Json<routes::LogFilterBody>,
+    users: web::Data<dyn UserRepository>,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, LogFilterError> {
+    init_request_trace!("Set log filter", %body.directive);
+    validate_admin_request_auth(request, users.get_ref()).await?;
+    routes::set_log_filter(body, log_filter).await
+}
+
+/// Recent audit events, newest first, optionally filtered by `actor` and
+/// `route`. Requires an admin's 'Basic' authorisation
+#[register_endpoint]
+#[add_path_const(scope = "/admin")]
+#[get("/audit_events")]
+pub async fn audit_events(
+    request: HttpRequest,
+    query: web::Query<AuditQuery>,
+    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
+) -> Result<HttpResponse, AuditError> {
+    init_request_trace!("Get audit events", ?query);
+    validate_admin_request_auth(request, users.get_ref()).await?;
+    routes::audit_events(query, pool).await
+}
+
+#[register_endpoint]
 #[add_path_const]
 #[get("/home")]
 pub async fn home() -> HttpResponse {
@@ -57,6 +142,7 @@ pub async fn home() -> HttpResponse {
     routes::home().await
 }
 
+#[register_endpoint]
 #[add_path_const]
 #[get("/login")]
 pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
@@ -64,20 +150,22 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
     routes::login::login_form(flash_messages).await
 }
 
+#[register_endpoint(audit)]
 #[add_path_const]
 #[post("/login")]
 pub async fn login(
+    request: HttpRequest,
     form: web::Form<routes::login::FormData>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
 ) -> Result<HttpResponse, LoginError> {
-    init_request_trace!("Login Attempt", %form.username);
-    let login_result = routes::login::login(form, pool).await;
+    init_sensitive_request_trace!("Login Attempt", [username = form.username]);
+    let login_result = routes::login::login(request, form, users).await;
     match login_result {
         Ok(response) => Ok(response),
         Err(e) => {
             FlashMessage::error(e.to_string()).send();
             let response = HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/login"))
+                .insert_header((LOCATION, login_form::url()))
                 // .cookie(Cookie::new("_flash", e.to_string()))
                 .finish();
 
@@ -0,0 +1,420 @@
+use crate::audit::bytes_to_payload;
+use crate::auth::{validate_request_auth, AuthenticatedUser};
+use crate::clock::Clock;
+use crate::registry;
+use crate::repository::UserRepository;
+use crate::routes::IdempotencyError;
+use actix_web::body::{to_bytes, EitherBody, MessageBody};
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::{HeaderValue, AUTHORIZATION};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::HttpMessage;
+use actix_web::{Error, HttpResponse};
+use anyhow::{anyhow, Context};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::rc::Rc;
+use std::sync::Arc;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Set on responses replayed for a repeated key
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+/// Longer keys are rejected
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Client-chosen key identifying a request, so retries can be detected.
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryFrom<&HeaderValue> for IdempotencyKey {
+    type Error = IdempotencyError;
+
+    fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
+        let key = value
+            .to_str()
+            .map_err(|_| IdempotencyError::InvalidKey("not ASCII".into()))?;
+        match key.len() {
+            0 => Err(IdempotencyError::InvalidKey("empty".into())),
+            len if len > MAX_KEY_LENGTH => Err(IdempotencyError::InvalidKey(
+                format!("longer than {MAX_KEY_LENGTH} characters"),
+            )),
+            _ => Ok(Self(key.to_string())),
+        }
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Middleware replaying the saved response to requests which repeat an
+/// `Idempotency-Key` header, for endpoints registered with
+/// `#[register_endpoint(idempotent)]`.
+///
+/// Keys are scoped to the user authenticated by the request's 'Basic'
+/// credentials, so responses are only replayed to the user who made the
+/// original request. Anonymous requests' keys are also scoped by the request,
+/// so they're only replayed to identical requests. While a request is in
+/// flight, requests repeating its key are rejected with 409, and a user's keys
+/// reused for a different request are rejected with 422. Server errors aren't
+/// saved, so the request can be retried.
+pub struct Idempotency {
+    pool: PgPool,
+    users: Arc<dyn UserRepository>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Idempotency {
+    pub fn new(
+        pool: PgPool,
+        users: Arc<dyn UserRepository>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self { pool, users, clock }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            pool: self.pool.clone(),
+            users: self.users.clone(),
+            clock: self.clock.clone(),
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    pool: PgPool,
+    users: Arc<dyn UserRepository>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let method = request.method().to_string();
+        let idempotent = request
+            .match_pattern()
+            .is_some_and(|route| registry::is_idempotent(&method, &route));
+        let key = match (idempotent, request.headers().get(IDEMPOTENCY_KEY)) {
+            (true, Some(key)) => IdempotencyKey::try_from(key),
+            _ => {
+                let response = self.service.call(request);
+                return Box::pin(async move {
+                    response.await.map(ServiceResponse::map_into_left_body)
+                });
+            }
+        };
+        let service = self.service.clone();
+        let pool = self.pool.clone();
+        let users = self.users.clone();
+        let clock = self.clock.clone();
+
+        Box::pin(async move {
+            let key = key?;
+            let requester = requester(&request, users.as_ref()).await?;
+            let body = request.extract::<Bytes>().await?;
+            let request_hash = hash_request(&request, &body);
+            request.set_payload(bytes_to_payload(body));
+            let saved = Saved {
+                pool,
+                requester,
+                key,
+                request_hash,
+            };
+
+            // Claimed by a committed row, so no connection is held while the
+            // endpoint runs
+            while !saved.claim(clock.as_ref()).await? {
+                // Released since, so claim it again
+                if let Some(existing) = saved.get().await? {
+                    let response = existing.into_response()?;
+                    return Ok(request
+                        .into_response(response)
+                        .map_into_right_body());
+                }
+            }
+
+            let response = match service.call(request).await {
+                Ok(response) => response,
+                Err(e) => {
+                    saved.release().await?;
+                    return Err(e);
+                }
+            };
+            // Not saved, so the request can be retried once the error's fixed
+            if response.status().is_server_error() {
+                saved.release().await?;
+                return Ok(response.map_into_left_body());
+            }
+            let (request, response) = response.into_parts();
+            let (head, body) = response.into_parts();
+            let body = match to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    saved.release().await?;
+                    return Err(IdempotencyError::UnexpectedError(anyhow!(
+                        "Failed to read response body: {}",
+                        e.into()
+                    ))
+                    .into());
+                }
+            };
+            let response = head.set_body(body);
+            saved.save(&response).await?;
+
+            Ok(
+                ServiceResponse::new(request, response.map_into_boxed_body())
+                    .map_into_right_body(),
+            )
+        })
+    }
+}
+
+/// The authenticated user's username, or empty for anonymous requests.
+async fn requester(
+    request: &ServiceRequest,
+    users: &dyn UserRepository,
+) -> Result<String, Error> {
+    if !request.headers().contains_key(AUTHORIZATION) {
+        return Ok(String::new());
+    }
+    validate_request_auth(request.request().clone(), users).await?;
+    let user = request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.0.clone())
+        .context("Authenticated user missing from request")
+        .map_err(IdempotencyError::from)?;
+    Ok(user)
+}
+
+/// Identifies the request, so a key reused for another request is detected.
+fn hash_request(request: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(request.method().as_str());
+    hasher.update(request.uri().to_string());
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// A request's row in the `idempotency` table.
+struct Saved {
+    pool: PgPool,
+    requester: String,
+    key: IdempotencyKey,
+    request_hash: String,
+}
+
+impl Saved {
+    /// Insert the row without a response, marking the request as in
+    /// progress, returning whether the key was claimed.
+    async fn claim(&self, clock: &dyn Clock) -> Result<bool, IdempotencyError> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO idempotency
+                (requester, idempotency_key, request_hash, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING
+            "#,
+            self.requester,
+            self.key.as_ref(),
+            self.request_hash,
+            clock.now(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to claim idempotency key")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The response saved for the key, failing if its request is in progress
+    /// or it was reused for a different request. Anonymous requests
+    /// only share keys with identical requests.
+    async fn get(&self) -> Result<Option<SavedResponse>, IdempotencyError> {
+        let saved = sqlx::query!(
+            r#"
+            SELECT request_hash, response_status, response_headers,
+                response_body
+            FROM idempotency
+            WHERE requester = $1 AND idempotency_key = $2
+                AND (requester <> '' OR request_hash = $3)
+            "#,
+            self.requester,
+            self.key.as_ref(),
+            self.request_hash,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to fetch saved response")?;
+        let saved = match saved {
+            Some(saved) => saved,
+            None => return Ok(None),
+        };
+        match (
+            saved.response_status,
+            saved.response_headers,
+            saved.response_body,
+        ) {
+            (Some(_), Some(_), Some(_))
+                if saved.request_hash != self.request_hash =>
+            {
+                Err(IdempotencyError::KeyReused)
+            }
+            (Some(response_status), Some(response_headers), Some(body)) => {
+                Ok(Some(SavedResponse {
+                    response_status,
+                    response_headers,
+                    response_body: body,
+                }))
+            }
+            _ => Err(IdempotencyError::InProgress),
+        }
+    }
+
+    /// Delete the row, so the request can be retried.
+    async fn release(&self) -> Result<(), IdempotencyError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE requester = $1 AND idempotency_key = $2
+                AND request_hash = $3
+            "#,
+            self.requester,
+            self.key.as_ref(),
+            self.request_hash,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to release idempotency key")?;
+        Ok(())
+    }
+
+    async fn save(
+        &self,
+        response: &HttpResponse<Bytes>,
+    ) -> Result<(), IdempotencyError> {
+        // Headers which aren't visible ASCII can't be saved as JSON strings
+        let headers: Vec<(&str, &str)> = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((name.as_str(), value.to_str().ok()?))
+            })
+            .collect();
+        let headers = serde_json::to_value(headers)
+            .context("Failed to serialise response headers")?;
+        sqlx::query!(
+            r#"
+            UPDATE idempotency
+            SET response_status = $4, response_headers = $5,
+                response_body = $6
+            WHERE requester = $1 AND idempotency_key = $2
+                AND request_hash = $3
+            "#,
+            self.requester,
+            self.key.as_ref(),
+            self.request_hash,
+            response.status().as_u16() as i16,
+            headers,
+            response.body().as_ref(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save response")?;
+        Ok(())
+    }
+}
+
+struct SavedResponse {
+    response_status: i16,
+    response_headers: serde_json::Value,
+    response_body: Vec<u8>,
+}
+
+impl SavedResponse {
+    fn into_response(self) -> Result<HttpResponse, IdempotencyError> {
+        let status = StatusCode::from_u16(self.response_status as u16)
+            .context("Saved response has an invalid status")?;
+        let headers: Vec<(String, String)> =
+            serde_json::from_value(self.response_headers)
+                .context("Saved response has invalid headers")?;
+        let mut response = HttpResponse::build(status);
+        for (name, value) in headers {
+            response.append_header((name, value));
+        }
+        response.insert_header((IDEMPOTENT_REPLAYED, "true"));
+        Ok(response.body(self.response_body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_and_long_keys_are_invalid() {
+        let long = "a".repeat(MAX_KEY_LENGTH + 1);
+
+        assert!(
+            IdempotencyKey::try_from(&HeaderValue::from_static("")).is_err()
+        );
+        assert!(IdempotencyKey::try_from(
+            &HeaderValue::from_str(&long).unwrap()
+        )
+        .is_err());
+        assert!(
+            IdempotencyKey::try_from(&HeaderValue::from_static("abc")).is_ok()
+        );
+    }
+
+    #[test]
+    fn saved_responses_are_replayed_with_their_headers() {
+        let saved = SavedResponse {
+            response_status: 201,
+            response_headers: serde_json::json!([["x-saved", "yes"]]),
+            response_body: b"created".to_vec(),
+        };
+
+        let response = saved.into_response().unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        assert_eq!("yes", response.headers().get("x-saved").unwrap());
+        assert_eq!(
+            "true",
+            response.headers().get(IDEMPOTENT_REPLAYED).unwrap()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+mod send_email;
+mod worker;
+
+pub use send_email::*;
+pub use worker::*;
+
+use crate::clock::Clock;
+use crate::email::EmailSender;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Attempts before a job is dead-lettered, unless it sets its own
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+
+/// Dependencies available to jobs and scheduled tasks while they run.
+#[derive(Clone)]
+pub struct JobContext {
+    pub db_pool: PgPool,
+    pub clock: Arc<dyn Clock>,
+    pub email_sender: Arc<dyn EmailSender>,
+}
+
+/// Work run by the job workers outside the request cycle.
+///
+/// Implement this for new jobs, register them with `ApplicationBuilder::job`
+/// and add them to the queue with `enqueue`.
+#[async_trait::async_trait]
+pub trait Job: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Stored with each job to find its handler, so must be unique and
+    /// shouldn't change while jobs are queued
+    const KIND: &'static str;
+    /// Failed jobs are retried with backoff, then dead-lettered once they've
+    /// been attempted this many times
+    const MAX_ATTEMPTS: i32 = DEFAULT_MAX_ATTEMPTS;
+
+    async fn run(self, context: &JobContext) -> Result<(), anyhow::Error>;
+}
+
+/// Queue `job` to run once `run_at` has passed, returning its id.
+///
+/// Pass a transaction as the `executor` to only queue the job if the
+/// transaction commits.
+#[tracing::instrument(
+    name = "Enqueue job",
+    skip(job, executor),
+    fields(kind = J::KIND)
+)]
+pub async fn enqueue<J: Job>(
+    job: &J,
+    run_at: DateTime<Utc>,
+    executor: impl PgExecutor<'_>,
+) -> Result<Uuid, anyhow::Error> {
+    let id = Uuid::new_v4();
+    let payload =
+        serde_json::to_value(job).context("Failed to serialise job")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO jobs (id, kind, payload, max_attempts, run_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        id,
+        J::KIND,
+        payload,
+        J::MAX_ATTEMPTS,
+        run_at,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to insert job")?;
+    Ok(id)
+}
+
+type JobResult = Result<(), anyhow::Error>;
+type Handler =
+    dyn Fn(Value, JobContext) -> BoxFuture<'static, JobResult> + Send + Sync;
+
+/// The handler for each registered kind of job.
+#[derive(Clone, Default)]
+pub struct JobHandlers(HashMap<&'static str, Arc<Handler>>);
+
+impl JobHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<J: Job>(mut self) -> Self {
+        let handler: Arc<Handler> = Arc::new(|payload, context| {
+            Box::pin(async move {
+                let job: J = serde_json::from_value(payload)
+                    .context("Failed to deserialise job")?;
+                job.run(&context).await
+            })
+        });
+        if self.0.insert(J::KIND, handler).is_some() {
+            panic!("Job kind {} is registered twice", J::KIND);
+        }
+        self
+    }
+
+    /// Jobs without a handler fail, so are retried in case a newer version of
+    /// the app can run them.
+    async fn run(
+        &self,
+        kind: &str,
+        payload: Value,
+        context: &JobContext,
+    ) -> Result<(), anyhow::Error> {
+        match self.0.get(kind) {
+            Some(handler) => handler(payload, context.clone()).await,
+            None => Err(anyhow!("No handler is registered for {kind} jobs")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::email::LogEmailSender;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Succeeds {
+        value: u32,
+    }
+
+    #[async_trait::async_trait]
+    impl Job for Succeeds {
+        const KIND: &'static str = "succeeds";
+
+        async fn run(self, _context: &JobContext) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    fn context() -> JobContext {
+        JobContext {
+            db_pool: PgPool::connect_lazy("postgres://localhost").unwrap(),
+            clock: Arc::new(SystemClock),
+            email_sender: Arc::new(LogEmailSender),
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_run_with_their_registered_handler() {
+        let handlers = JobHandlers::new().register::<Succeeds>();
+        let payload = serde_json::json!({ "value": 1 });
+
+        assert!(handlers.run("succeeds", payload, &context()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn unregistered_and_malformed_jobs_fail() {
+        let handlers = JobHandlers::new().register::<Succeeds>();
+        let malformed = serde_json::json!({ "value": "one" });
+
+        assert!(handlers
+            .run("unknown", Value::Null, &context())
+            .await
+            .is_err());
+        assert!(handlers
+            .run("succeeds", malformed, &context())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn job_kinds_must_be_unique() {
+        JobHandlers::new()
+            .register::<Succeeds>()
+            .register::<Succeeds>();
+    }
+}
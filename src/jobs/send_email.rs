@@ -0,0 +1,23 @@
+use crate::domain::{Email, Parseable};
+use crate::jobs::{Job, JobContext};
+
+/// Send an email with the app's `EmailSender`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct SendEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait::async_trait]
+impl Job for SendEmail {
+    const KIND: &'static str = "send_email";
+
+    async fn run(self, context: &JobContext) -> Result<(), anyhow::Error> {
+        let recipient = Email::parse(self.recipient)?;
+        context
+            .email_sender
+            .send(&recipient, &self.subject, &self.body)
+            .await
+    }
+}
@@ -0,0 +1,284 @@
+use crate::configuration::JobsSettings;
+use crate::jobs::{JobContext, JobHandlers};
+use crate::shutdown::ShutdownHook;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use serde_json::Value;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Retry backoff stops doubling after this many attempts
+const MAX_BACKOFF_DOUBLINGS: u32 = 10;
+
+/// Workers running queued jobs, spawned alongside the server.
+///
+/// Each job is leased to the worker running it, so workers in other instances
+/// of the app skip it, and it's queued again once the lease expires if the
+/// worker dies before finishing.
+#[derive(Clone)]
+pub struct Workers {
+    settings: JobsSettings,
+    context: JobContext,
+    handlers: JobHandlers,
+    stop: Arc<watch::Sender<bool>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Workers {
+    pub fn new(
+        settings: JobsSettings,
+        context: JobContext,
+        handlers: JobHandlers,
+    ) -> Self {
+        Self {
+            settings,
+            context,
+            handlers,
+            stop: Arc::new(watch::channel(false).0),
+            tasks: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Spawn the configured number of workers, which run until this is
+    /// shut down.
+    pub fn spawn(&self) {
+        let mut tasks = self.tasks.lock().expect("Workers lock poisoned");
+        for _ in 0..self.settings.workers {
+            tasks.push(tokio::spawn(self.clone().work(self.stop.subscribe())));
+        }
+    }
+
+    async fn work(self, mut stop: watch::Receiver<bool>) {
+        let poll_interval =
+            Duration::from_millis(self.settings.poll_interval_ms);
+        while !*stop.borrow() {
+            if let Err(e) = requeue_expired_jobs(&self.context).await {
+                tracing::error!(error = ?e, "Failed to requeue expired jobs");
+            }
+            let ran_job = run_next_job(
+                &self.context,
+                &self.handlers,
+                Duration::from_secs(self.settings.retry_backoff_secs),
+                Duration::from_secs(self.settings.lease_secs),
+            )
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!(error = ?e, "Failed to run next job");
+                false
+            });
+            // Check for the next job straight away while the queue is busy
+            if !ran_job {
+                tokio::select! {
+                    _ = tokio::time::sleep(poll_interval) => {}
+                    _ = stop.changed() => {}
+                }
+            }
+        }
+    }
+}
+
+/// Stops the workers, waiting for the jobs they're running to finish.
+#[async_trait::async_trait]
+impl ShutdownHook for Workers {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    async fn shutdown(&self) -> Result<(), anyhow::Error> {
+        self.stop.send_replace(true);
+        let tasks = std::mem::take(
+            &mut *self.tasks.lock().expect("Workers lock poisoned"),
+        );
+        for task in tasks {
+            task.await.context("Job worker failed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the next job that's due, returning whether there was one.
+///
+/// The job is claimed for `lease` and its attempt counted before it runs, so
+/// no transaction is held open while it runs. Failed jobs are retried after
+/// `retry_backoff`, doubled for each earlier attempt, until they run out of
+/// attempts and are dead-lettered.
+pub async fn run_next_job(
+    context: &JobContext,
+    handlers: &JobHandlers,
+    retry_backoff: Duration,
+    lease: Duration,
+) -> Result<bool, anyhow::Error> {
+    let now = context.clock.now();
+    let locked_until = now
+        + chrono::Duration::from_std(lease).context("Job lease is too long")?;
+    let job = sqlx::query_as!(
+        ClaimedJob,
+        r#"
+        UPDATE jobs
+        SET status = 'running', attempts = attempts + 1, locked_until = $2
+        WHERE id = (
+            SELECT id
+            FROM jobs
+            WHERE status = 'queued' AND run_at <= $1
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, payload, attempts, max_attempts,
+            locked_until AS "locked_until!"
+        "#,
+        now,
+        locked_until,
+    )
+    .fetch_optional(&context.db_pool)
+    .await
+    .context("Failed to claim next job")?;
+    let job = match job {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+
+    run_job(job, context, handlers, retry_backoff).await?;
+    Ok(true)
+}
+
+/// Queue running jobs whose lease expired, as their worker died, or
+/// dead-letter them if they're out of attempts.
+pub async fn requeue_expired_jobs(
+    context: &JobContext,
+) -> Result<u64, anyhow::Error> {
+    let now = context.clock.now();
+    let requeued = sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = CASE
+                WHEN attempts >= max_attempts THEN 'dead'
+                ELSE 'queued'
+            END,
+            locked_until = NULL, run_at = $1,
+            last_error = 'Job lease expired before it finished'
+        WHERE status = 'running' AND locked_until < $1
+        "#,
+        now,
+    )
+    .execute(&context.db_pool)
+    .await
+    .context("Failed to requeue expired jobs")?
+    .rows_affected();
+    if requeued > 0 {
+        tracing::warn!(requeued, "Requeued jobs with expired leases");
+    }
+    Ok(requeued)
+}
+
+struct ClaimedJob {
+    id: Uuid,
+    kind: String,
+    payload: Value,
+    attempts: i32,
+    max_attempts: i32,
+    locked_until: DateTime<Utc>,
+}
+
+/// Run `job`, then delete, reschedule or dead-letter it, unless its lease
+/// expired and it was claimed again.
+#[tracing::instrument(
+    name = "Run job",
+    skip_all,
+    fields(id = %job.id, kind = %job.kind, attempt = job.attempts)
+)]
+async fn run_job(
+    job: ClaimedJob,
+    context: &JobContext,
+    handlers: &JobHandlers,
+    retry_backoff: Duration,
+) -> Result<(), anyhow::Error> {
+    let result =
+        AssertUnwindSafe(handlers.run(&job.kind, job.payload, context))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("Job panicked")));
+
+    let finished = match result {
+        Ok(()) => sqlx::query!(
+            r#"
+            DELETE FROM jobs
+            WHERE id = $1 AND status = 'running' AND locked_until = $2
+            "#,
+            job.id,
+            job.locked_until,
+        )
+        .execute(&context.db_pool)
+        .await
+        .context("Failed to delete completed job")?,
+        Err(e) if job.attempts >= job.max_attempts => {
+            tracing::error!(error = ?e, "Job failed, dead-lettering it");
+            sqlx::query!(
+                r#"
+                UPDATE jobs
+                SET status = 'dead', locked_until = NULL, last_error = $3
+                WHERE id = $1 AND status = 'running' AND locked_until = $2
+                "#,
+                job.id,
+                job.locked_until,
+                format!("{e:?}"),
+            )
+            .execute(&context.db_pool)
+            .await
+            .context("Failed to dead-letter job")?
+        }
+        Err(e) => {
+            let retry_at = context.clock.now()
+                + chrono::Duration::from_std(backoff(
+                    retry_backoff,
+                    job.attempts,
+                ))
+                .context("Retry backoff is too long")?;
+            tracing::warn!(error = ?e, %retry_at, "Job failed, retrying it");
+            sqlx::query!(
+                r#"
+                UPDATE jobs
+                SET status = 'queued', locked_until = NULL, last_error = $3,
+                    run_at = $4
+                WHERE id = $1 AND status = 'running' AND locked_until = $2
+                "#,
+                job.id,
+                job.locked_until,
+                format!("{e:?}"),
+                retry_at,
+            )
+            .execute(&context.db_pool)
+            .await
+            .context("Failed to reschedule job")?
+        }
+    };
+    if finished.rows_affected() == 0 {
+        tracing::warn!("Job's lease expired before it finished");
+    }
+    Ok(())
+}
+
+/// Delay before retrying a job that's been attempted `attempts` times.
+fn backoff(retry_backoff: Duration, attempts: i32) -> Duration {
+    let doublings = (attempts.max(1) as u32 - 1).min(MAX_BACKOFF_DOUBLINGS);
+    retry_backoff * 2u32.pow(doublings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_with_each_attempt_up_to_a_limit() {
+        let base = Duration::from_secs(10);
+
+        assert_eq!(Duration::from_secs(10), backoff(base, 1));
+        assert_eq!(Duration::from_secs(40), backoff(base, 3));
+        assert_eq!(backoff(base, 11), backoff(base, 50));
+    }
+}
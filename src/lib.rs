@@ -1,7 +1,22 @@
+pub mod api_version;
+pub mod audit;
 pub mod auth;
+pub mod cli;
+pub mod clock;
 pub mod configuration;
+pub mod database;
 pub mod domain;
+pub mod email;
 pub mod endpoint;
+pub mod idempotency;
+pub mod jobs;
+pub mod metrics;
+pub mod migrations;
+pub mod readiness;
+pub mod registry;
+pub mod repository;
 pub mod routes;
+pub mod scheduler;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
@@ -1,28 +1,93 @@
-use actix_web_template::configuration::{HmacSecret, Settings};
-use actix_web_template::startup::run;
-use actix_web_template::telemetry::{get_subscriber, init_subscriber};
-use sqlx::postgres::PgPoolOptions;
-use std::net::TcpListener;
+use actix_web_template::cli::{self, Cli, Command};
+use actix_web_template::configuration::{Settings, SettingsError};
+use actix_web_template::repository::PgUserRepository;
+use actix_web_template::startup::{connect, get_connection_pool, Application};
+use actix_web_template::telemetry::{
+    get_subscriber, init_subscriber, init_tracer_provider, make_writer,
+};
+use clap::Parser;
+use opentelemetry::trace::TracerProvider;
+use secrecy::Secret;
+use std::io::IsTerminal;
 
 const APP_NAME: &str = "example-app";
-const DEFAULT_LOG_LEVEL: &str = "info";
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber(
+async fn main() -> Result<(), anyhow::Error> {
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(get_config()?).await?,
+        Command::Migrate { command } => {
+            let pool = get_connection_pool(&get_config()?.database);
+            println!("{}", cli::migrate(&pool, command).await?);
+        }
+        Command::CreateUser { username, admin } => {
+            let users =
+                PgUserRepository(get_connection_pool(&get_config()?.database));
+            cli::create_user(&users, username, read_password()?, admin).await?;
+        }
+        Command::ResetPassword { username } => {
+            let users =
+                PgUserRepository(get_connection_pool(&get_config()?.database));
+            cli::reset_password(&users, username, read_password()?).await?;
+        }
+        Command::CheckConfig => {
+            println!("{}", cli::check_config(&get_config()?)?)
+        }
+        Command::Routes => println!("{}", cli::routes()),
+    }
+    Ok(())
+}
+
+fn get_config() -> Result<Settings, SettingsError> {
+    let settings = Settings::get_config()?;
+    // Printed rather than logged, as the subscriber is configured by the
+    // settings
+    for warning in &settings.warnings {
+        eprintln!("warning: {warning}");
+    }
+    Ok(settings)
+}
+
+async fn serve(configuration: Settings) -> std::io::Result<()> {
+    let tracer_provider =
+        init_tracer_provider(APP_NAME.into(), &configuration.telemetry)
+            .expect("Failed to initialise tracer provider");
+    let writer = make_writer(&configuration.logging.destination)
+        .expect("Failed to open log destination");
+    let (subscriber, log_filter) = get_subscriber(
         APP_NAME.into(),
-        DEFAULT_LOG_LEVEL.into(),
-        std::io::stdout,
+        &configuration.logging,
+        writer,
+        tracer_provider.tracer(APP_NAME),
     );
     init_subscriber(subscriber);
 
-    let configuration =
-        Settings::get_config().expect("Failed to load configuration");
+    let db_pool = connect(&configuration.database)
+        .await
+        .map_err(std::io::Error::other)?;
+    let application = Application::builder(configuration, log_filter)
+        .db_pool(db_pool)
+        .build()?;
+    let result = application.run_until_stopped().await;
 
-    let db_pool = PgPoolOptions::new()
-        .acquire_timeout(std::time::Duration::from_secs(2))
-        .connect_lazy_with(configuration.database.with_db());
+    // Flush any spans still waiting to be exported
+    if let Err(e) = tracer_provider.shutdown() {
+        tracing::error!(error = ?e, "Failed to shut down tracer provider");
+    }
+    result
+}
 
-    let listener = TcpListener::bind(configuration.get_address())?;
-    run(listener, db_pool, HmacSecret(configuration.app.hmac_secret))?.await
+/// Read a password from the first line of stdin, so it isn't left in the
+/// shell history. It isn't echoed if stdin is a terminal, otherwise it's read
+/// as is, e.g. when piped in.
+fn read_password() -> std::io::Result<Secret<String>> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return rpassword::prompt_password("Password: ").map(Secret::new);
+    }
+    let mut password = String::new();
+    stdin.read_line(&mut password)?;
+    Ok(Secret::new(
+        password.trim_end_matches(['\r', '\n']).to_string(),
+    ))
 }
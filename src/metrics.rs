@@ -0,0 +1,141 @@
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::Error;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+use sqlx::PgPool;
+use std::time::Instant;
+
+/// Route label for requests which didn't match an endpoint, to keep the
+/// number of label values bounded.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests handled.",
+        &["method", "route", "status"]
+    )
+    .expect("Failed to register http_requests_total")
+});
+
+static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency.",
+        &["method", "route"]
+    )
+    .expect("Failed to register http_request_duration_seconds")
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "db_pool_connections",
+        "Connections in the Postgres pool.",
+        &["state"]
+    )
+    .expect("Failed to register db_pool_connections")
+});
+
+static DB_QUERY_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "db_query_duration_seconds",
+        "Database query latency.",
+        &["query"]
+    )
+    .expect("Failed to register db_query_duration_seconds")
+});
+
+/// Times a database query until dropped, e.g. at the end of `read_db`.
+/// ```rust
+/// # use actix_web_template::metrics::db_query_timer;
+/// let _timer = db_query_timer("read_db");
+/// ```
+pub fn db_query_timer(query: &str) -> HistogramTimer {
+    DB_QUERY_DURATION_SECONDS
+        .with_label_values(&[query])
+        .start_timer()
+}
+
+/// Render every registered metric in the Prometheus text format.
+pub fn render(pool: &PgPool) -> Result<String, anyhow::Error> {
+    let idle = pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["active"])
+        .set(pool.size() as i64 - idle);
+
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Middleware recording the count and latency of requests, labelled by the
+/// matched route template (i.e. the endpoint's `PATH`) rather than the raw
+/// path.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = request.method().to_string();
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let response = self.service.call(request);
+
+        Box::pin(async move {
+            let response = response.await;
+            let status = match &response {
+                Ok(response) => response.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+
+            HTTP_REQUESTS_TOTAL
+                .with_label_values(&[&method, &route, status.as_str()])
+                .inc();
+            HTTP_REQUEST_DURATION_SECONDS
+                .with_label_values(&[&method, &route])
+                .observe(start.elapsed().as_secs_f64());
+            response
+        })
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Context;
+use itertools::Itertools;
+use sqlx::migrate::{AppliedMigration, Migrate, Migration, Migrator};
+use sqlx::PgPool;
+use std::fmt::{Display, Formatter};
+
+/// The migrations in `migrations/`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Apply any pending migrations.
+///
+/// The migrator holds a Postgres advisory lock while it runs, so replicas
+/// starting together apply migrations one at a time, and replicas after the
+/// first find nothing left to apply.
+#[tracing::instrument(name = "Run migrations", skip_all)]
+pub async fn run(pool: &PgPool) -> Result<(), anyhow::Error> {
+    MIGRATOR.run(pool).await.context("Failed to run migrations")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the file has changed since
+    ChecksumMismatch,
+    /// Failed part way through, so needs fixing by hand
+    Failed,
+    /// Applied, but no longer in `migrations/`
+    Missing,
+}
+
+impl Display for MigrationState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::ChecksumMismatch => "checksum mismatch",
+            MigrationState::Failed => "failed",
+            MigrationState::Missing => "missing from migrations/",
+        };
+        f.write_str(state)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    /// Empty for `MigrationState::Missing` migrations
+    pub description: String,
+    pub state: MigrationState,
+}
+
+impl Display for MigrationStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ({})", self.version, self.description, self.state)
+    }
+}
+
+/// Status of each migration, and whether migrations were ever run.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Created the first time migrations are run
+    pub has_migrations_table: bool,
+    /// Ordered by version
+    pub migrations: Vec<MigrationStatus>,
+}
+
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.has_migrations_table {
+            writeln!(f, "No migrations table, migrations were never run")?;
+        }
+        write!(f, "{}", self.migrations.iter().join("\n"))
+    }
+}
+
+/// Compare the migrations in `migrations/` with those applied to the
+/// database, ordered by version.
+///
+/// Reads the database only, so a new database is reported as having no
+/// migrations table, with every migration pending.
+#[tracing::instrument(name = "Get migration status", skip_all)]
+pub async fn status(pool: &PgPool) -> Result<MigrationReport, anyhow::Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire database connection")?;
+    let has_migrations_table: bool = sqlx::query_scalar(
+        "SELECT to_regclass('_sqlx_migrations') IS NOT NULL",
+    )
+    .fetch_one(&mut connection)
+    .await
+    .context("Failed to check for migrations table")?;
+    if !has_migrations_table {
+        return Ok(MigrationReport {
+            has_migrations_table,
+            migrations: compare(MIGRATOR.iter(), &[], None),
+        });
+    }
+    let applied = connection
+        .list_applied_migrations()
+        .await
+        .context("Failed to list applied migrations")?;
+    let failed = connection
+        .dirty_version()
+        .await
+        .context("Failed to check for failed migrations")?;
+    Ok(MigrationReport {
+        has_migrations_table,
+        migrations: compare(MIGRATOR.iter(), &applied, failed),
+    })
+}
+
+fn compare<'a>(
+    migrations: impl Iterator<Item = &'a Migration>,
+    applied: &[AppliedMigration],
+    failed: Option<i64>,
+) -> Vec<MigrationStatus> {
+    let mut statuses: Vec<MigrationStatus> = migrations
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let state = match applied
+                .iter()
+                .find(|applied| applied.version == migration.version)
+            {
+                _ if failed == Some(migration.version) => {
+                    MigrationState::Failed
+                }
+                Some(applied) if applied.checksum != migration.checksum => {
+                    MigrationState::ChecksumMismatch
+                }
+                Some(_) => MigrationState::Applied,
+                None => MigrationState::Pending,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    for applied in applied {
+        if !statuses
+            .iter()
+            .any(|status| status.version == applied.version)
+        {
+            statuses.push(MigrationStatus {
+                version: applied.version,
+                description: String::new(),
+                state: MigrationState::Missing,
+            });
+        }
+    }
+    statuses.sort_by_key(|status| status.version);
+    statuses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::migrate::MigrationType;
+    use std::borrow::Cow;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            Cow::Borrowed("migration"),
+            MigrationType::Simple,
+            Cow::Borrowed(sql),
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            checksum: migration.checksum.clone(),
+        }
+    }
+
+    #[test]
+    fn migrations_are_compared_with_those_applied() {
+        let migrations = [
+            migration(1, "SELECT 1"),
+            migration(2, "SELECT 2"),
+            migration(3, "SELECT 3"),
+            migration(5, "SELECT 5"),
+        ];
+        let applied = [
+            applied(&migrations[0]),
+            applied(&migration(2, "SELECT 'changed'")),
+            applied(&migrations[2]),
+            applied(&migration(4, "SELECT 4")),
+        ];
+
+        let states: Vec<(i64, MigrationState)> =
+            compare(migrations.iter(), &applied, Some(3))
+                .into_iter()
+                .map(|status| (status.version, status.state))
+                .collect();
+
+        assert_eq!(
+            vec![
+                (1, MigrationState::Applied),
+                (2, MigrationState::ChecksumMismatch),
+                (3, MigrationState::Failed),
+                (4, MigrationState::Missing),
+                (5, MigrationState::Pending),
+            ],
+            states
+        );
+    }
+}
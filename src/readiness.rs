@@ -0,0 +1,279 @@
+use crate::migrations::MIGRATOR;
+use crate::shutdown::Shutdown;
+use actix_web::rt::time::timeout;
+use anyhow::{anyhow, Context};
+use sqlx::migrate::Migrate;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Checks slower than this are treated as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A dependency which must be available for the app to serve requests.
+///
+/// Implement this for new dependencies and submit a `RegisteredCheck` to build
+/// it, which adds it to the app's checks.
+/// ```ignore
+/// inventory::submit!(RegisteredCheck(|deps| {
+///     Arc::new(PostgresCheck(deps.db_pool.clone()))
+/// }));
+/// ```
+#[async_trait::async_trait]
+pub trait ReadinessCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Failing critical checks make the app unready, other failures are only
+    /// reported.
+    fn critical(&self) -> bool {
+        true
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error>;
+}
+
+/// Builds a readiness check from the app's components, see `ReadinessCheck`.
+pub struct RegisteredCheck(
+    pub fn(&CheckDependencies) -> Arc<dyn ReadinessCheck>,
+);
+
+inventory::collect!(RegisteredCheck);
+
+/// Components registered checks are built from
+pub struct CheckDependencies {
+    pub db_pool: PgPool,
+    pub shutdown: Shutdown,
+}
+
+/// Every registered readiness check, reported on by the `ready` endpoint.
+#[derive(Clone, Default)]
+pub struct ReadinessChecks(Vec<Arc<dyn ReadinessCheck>>);
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub checks: Vec<CheckReport>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CheckReport {
+    pub name: String,
+    pub critical: bool,
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+impl ReadinessChecks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every check submitted as a `RegisteredCheck`.
+    pub fn registered(deps: &CheckDependencies) -> Self {
+        Self(
+            inventory::iter::<RegisteredCheck>
+                .into_iter()
+                .map(|RegisteredCheck(build)| build(deps))
+                .collect(),
+        )
+    }
+
+    pub fn register(mut self, check: impl ReadinessCheck + 'static) -> Self {
+        self.0.push(Arc::new(check));
+        self
+    }
+
+    /// Run every check concurrently.
+    #[tracing::instrument(name = "Run readiness checks", skip(self))]
+    pub async fn run(&self) -> ReadinessReport {
+        let checks =
+            futures::future::join_all(self.0.iter().map(run_check)).await;
+        let ready = checks
+            .iter()
+            .all(|check| !check.critical || check.status == CheckStatus::Ok);
+        ReadinessReport { ready, checks }
+    }
+}
+
+async fn run_check(check: &Arc<dyn ReadinessCheck>) -> CheckReport {
+    let start = Instant::now();
+    let result = timeout(CHECK_TIMEOUT, check.check())
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("Timed out after {CHECK_TIMEOUT:?}")));
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    // Errors can reveal hosts and credentials, so are only logged
+    if let Err(e) = &result {
+        tracing::warn!(check = check.name(), error = ?e, "Readiness check failed");
+    }
+    CheckReport {
+        name: check.name().to_string(),
+        critical: check.critical(),
+        status: match result {
+            Ok(_) => CheckStatus::Ok,
+            Err(_) => CheckStatus::Failed,
+        },
+        latency_ms,
+    }
+}
+
+/// Postgres accepts connections and queries.
+pub struct PostgresCheck(pub PgPool);
+
+inventory::submit!(RegisteredCheck(|deps| {
+    Arc::new(PostgresCheck(deps.db_pool.clone()))
+}));
+
+#[async_trait::async_trait]
+impl ReadinessCheck for PostgresCheck {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        sqlx::query("SELECT 1")
+            .execute(&self.0)
+            .await
+            .context("Failed to query database")?;
+        Ok(())
+    }
+}
+
+/// Every migration in `migrations/` has been applied to the database.
+pub struct MigrationsCheck(pub PgPool);
+
+inventory::submit!(RegisteredCheck(|deps| {
+    Arc::new(MigrationsCheck(deps.db_pool.clone()))
+}));
+
+#[async_trait::async_trait]
+impl ReadinessCheck for MigrationsCheck {
+    fn name(&self) -> &'static str {
+        "migrations"
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        let mut connection = self
+            .0
+            .acquire()
+            .await
+            .context("Failed to acquire database connection")?;
+        let applied: Vec<i64> = connection
+            .list_applied_migrations()
+            .await
+            .context("Failed to list applied migrations")?
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect();
+        let pending: Vec<i64> = MIGRATOR
+            .iter()
+            .map(|migration| migration.version)
+            .filter(|version| !applied.contains(version))
+            .collect();
+
+        match pending.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!("Pending migrations: {pending:?}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StubCheck {
+        critical: bool,
+        healthy: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl ReadinessCheck for StubCheck {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        fn critical(&self) -> bool {
+            self.critical
+        }
+
+        async fn check(&self) -> Result<(), anyhow::Error> {
+            match self.healthy {
+                true => Ok(()),
+                false => Err(anyhow!("Stub is unhealthy")),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn failing_critical_check_is_not_ready() {
+        let report = ReadinessChecks::new()
+            .register(StubCheck {
+                critical: true,
+                healthy: true,
+            })
+            .register(StubCheck {
+                critical: true,
+                healthy: false,
+            })
+            .run()
+            .await;
+
+        assert!(!report.ready);
+        assert_eq!(CheckStatus::Failed, report.checks[1].status);
+    }
+
+    #[actix_web::test]
+    async fn errors_are_not_reported() {
+        let report = ReadinessChecks::new()
+            .register(StubCheck {
+                critical: true,
+                healthy: false,
+            })
+            .run()
+            .await;
+
+        let report = serde_json::to_string(&report).unwrap();
+        assert!(!report.contains("Stub is unhealthy"), "{report}");
+    }
+
+    #[actix_web::test]
+    async fn dependencies_register_their_checks() {
+        let deps = CheckDependencies {
+            db_pool: sqlx::postgres::PgPoolOptions::new()
+                .connect_lazy("postgres://postgres@127.0.0.1:1/ready")
+                .unwrap(),
+            shutdown: Shutdown::new(Default::default()).without_signals(),
+        };
+
+        let mut names: Vec<_> = ReadinessChecks::registered(&deps)
+            .0
+            .iter()
+            .map(|check| check.name())
+            .collect();
+        names.sort();
+
+        assert_eq!(vec!["migrations", "postgres", "shutdown"], names);
+    }
+
+    #[actix_web::test]
+    async fn failing_non_critical_check_is_still_ready() {
+        let report = ReadinessChecks::new()
+            .register(StubCheck {
+                critical: false,
+                healthy: false,
+            })
+            .run()
+            .await;
+
+        assert!(report.ready);
+        assert_eq!(CheckStatus::Failed, report.checks[0].status);
+    }
+}
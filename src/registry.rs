@@ -0,0 +1,285 @@
+use crate::api_version::{self, ApiVersion};
+use crate::configuration::ApiSettings;
+use actix_web::web::{self, ServiceConfig};
+use std::collections::BTreeMap;
+
+/// An endpoint registered with `#[register_endpoint]`.
+pub struct Endpoint {
+    /// Name of the handler function
+    pub name: &'static str,
+    /// Full path the endpoint is mounted at, including any scope and version
+    pub path: &'static str,
+    /// Scope the endpoint is mounted in, or `""` for the app root. Versioned
+    /// endpoints' scopes are relative to the version's scope.
+    pub scope: &'static str,
+    /// Versioned endpoints are registered once for each version
+    pub version: Option<ApiVersion>,
+    pub methods: &'static [&'static str],
+    /// Requests are recorded in the audit log, see `audit::AuditLog`
+    pub audit: bool,
+    /// Responses are replayed for repeated `Idempotency-Key`s, see
+    /// `idempotency::Idempotency`
+    pub idempotent: bool,
+    /// Requests are run in a `UnitOfWork`, see `database::Transactional`
+    pub transactional: bool,
+    /// Mounts the endpoint's service on the app, or within its scope
+    pub register: fn(&mut ServiceConfig),
+}
+
+inventory::collect!(Endpoint);
+
+impl Endpoint {
+    /// Path the endpoint is also mounted at if it belongs to
+    /// `ApiVersion::UNVERSIONED`, see `api_version::unversioned_scope`.
+    fn unversioned_path(&self) -> Option<&'static str> {
+        match self.version {
+            Some(ApiVersion::UNVERSIONED) => self
+                .path
+                .strip_prefix(ApiVersion::UNVERSIONED.scope().as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// All endpoints annotated with `#[register_endpoint]`, in no particular order.
+pub fn endpoints() -> impl Iterator<Item = &'static Endpoint> {
+    inventory::iter::<Endpoint>.into_iter()
+}
+
+/// Whether requests to the endpoint matching the route template `path` and
+/// `method` are audited.
+pub fn is_audited(method: &str, path: &str) -> bool {
+    find(method, path).is_some_and(|endpoint| endpoint.audit)
+}
+
+/// Whether the endpoint matching the route template `path` and `method`
+/// replays responses for repeated `Idempotency-Key`s.
+pub fn is_idempotent(method: &str, path: &str) -> bool {
+    find(method, path).is_some_and(|endpoint| endpoint.idempotent)
+}
+
+/// Whether the endpoint matching the route template `path` and `method` runs
+/// requests in a `UnitOfWork`.
+pub fn is_transactional(method: &str, path: &str) -> bool {
+    find(method, path).is_some_and(|endpoint| endpoint.transactional)
+}
+
+/// Unversioned paths match the endpoint they're an alias of.
+fn find(method: &str, path: &str) -> Option<&'static Endpoint> {
+    endpoints().find(|endpoint| {
+        let matches_path =
+            endpoint.path == path || endpoint.unversioned_path() == Some(path);
+        matches_path && endpoint.methods.contains(&method)
+    })
+}
+
+/// Mount every registered endpoint that doesn't belong to an API version, for
+/// use with `App::configure`.
+pub fn configure(config: &mut ServiceConfig) {
+    mount(
+        config,
+        endpoints().filter(|endpoint| endpoint.version.is_none()),
+    );
+}
+
+/// Mount every registered endpoint, for use with `App::configure`, so the
+/// app's routes are exactly the registered endpoints.
+pub fn configure_app(config: &mut ServiceConfig, api_settings: &ApiSettings) {
+    configure(config);
+    // Mount each version's endpoints under its own scope, e.g. `/v1`
+    for version in ApiVersion::ALL {
+        config.service(api_version::scope(version, api_settings));
+    }
+    // Matches every path, so is mounted last
+    config.service(api_version::unversioned_scope(api_settings));
+}
+
+/// Mount every endpoint registered for `version`, relative to the version's
+/// scope. See `api_version::scope`.
+pub fn configure_version(config: &mut ServiceConfig, version: ApiVersion) {
+    mount(
+        config,
+        endpoints().filter(|endpoint| endpoint.version == Some(version)),
+    );
+}
+
+/// Endpoints sharing a scope are mounted within a single `web::scope`, as
+/// actix won't fall through to a second scope with the same prefix.
+fn mount<'a>(
+    config: &mut ServiceConfig,
+    endpoints: impl Iterator<Item = &'a Endpoint>,
+) {
+    let mut scopes: BTreeMap<&str, Vec<&Endpoint>> = BTreeMap::new();
+    for endpoint in endpoints {
+        scopes.entry(endpoint.scope).or_default().push(endpoint);
+    }
+
+    if let Some(root_endpoints) = scopes.remove("") {
+        for endpoint in root_endpoints {
+            (endpoint.register)(config);
+        }
+    }
+    // Reverse order mounts nested scopes, e.g. `/api/v1`, before `/api`
+    for (scope, scope_endpoints) in scopes.into_iter().rev() {
+        config.service(web::scope(scope).configure(|config| {
+            for endpoint in scope_endpoints {
+                (endpoint.register)(config);
+            }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::cookie::Key;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use actix_web_flash_messages::storage::CookieMessageStore;
+    use actix_web_flash_messages::FlashMessagesFramework;
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+
+    /// Replace `{param}` segments with a dummy value so the path can be
+    /// requested.
+    fn fill_path_params(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment.starts_with('{') {
+                true => "param",
+                false => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn endpoints_are_registered_once() {
+        let mut seen = HashSet::new();
+        for endpoint in endpoints() {
+            for method in endpoint.methods {
+                assert!(
+                    seen.insert((endpoint.path, *method)),
+                    "{method} {} is registered more than once",
+                    endpoint.path
+                );
+            }
+        }
+        assert!(seen.contains(&(crate::endpoint::health_check::PATH, "GET")));
+    }
+
+    #[test]
+    fn only_opted_in_endpoints_are_audited() {
+        use crate::endpoint::{example_get, example_post, login};
+
+        assert!(is_audited("POST", example_post::V1_PATH));
+        assert!(is_audited("POST", example_post::V2_PATH));
+        assert!(is_audited("POST", "/example_post"));
+        assert!(is_audited("POST", login::PATH));
+        assert!(!is_audited("GET", example_get::PATH));
+        // Methods sharing a path are audited separately
+        assert!(!is_audited("GET", login::PATH));
+    }
+
+    #[test]
+    fn only_opted_in_endpoints_are_idempotent() {
+        use crate::endpoint::{example_get, example_post};
+
+        assert!(is_idempotent("POST", example_post::V1_PATH));
+        assert!(!is_idempotent("GET", example_get::PATH));
+    }
+
+    #[actix_web::test]
+    async fn no_registered_endpoint_is_missing_from_app() {
+        let message_store =
+            CookieMessageStore::builder(Key::generate()).build();
+        let message_framework =
+            FlashMessagesFramework::builder(message_store).build();
+        let app = App::new()
+            .wrap(message_framework)
+            .configure(|config| configure_app(config, &ApiSettings::default()));
+        let app = init_service(app).await;
+
+        for endpoint in endpoints() {
+            for method in endpoint.methods {
+                let request = TestRequest::default()
+                    .method(Method::from_bytes(method.as_bytes()).unwrap())
+                    .uri(&fill_path_params(endpoint.path))
+                    .to_request();
+                let response = call_service(&app, request).await;
+
+                // Unmatched routes fall through to the default 404 service,
+                // matched routes without app data fail with 500 instead.
+                assert_ne!(
+                    StatusCode::NOT_FOUND,
+                    response.status(),
+                    "{method} {} ({}) is not mounted",
+                    endpoint.path,
+                    endpoint.name
+                );
+            }
+        }
+    }
+
+    /// Handlers given a route by actix's macros, e.g. `#[get("/path")]`, are
+    /// mounted by `configure_app` only if they're registered.
+    #[test]
+    fn every_routed_handler_is_registered() {
+        let registered: HashSet<&str> =
+            endpoints().map(|endpoint| endpoint.name).collect();
+        let mut handlers = vec![];
+        for file in
+            source_files(Path::new(env!("CARGO_MANIFEST_DIR")).join("src"))
+        {
+            let source = std::fs::read_to_string(&file).unwrap();
+            let mut routed = false;
+            for line in source.lines().map(str::trim) {
+                if ROUTE_MACROS.iter().any(|route| line.starts_with(route)) {
+                    routed = true;
+                } else if let Some((_, rest)) =
+                    line.split_once("fn ").filter(|_| routed)
+                {
+                    let name = rest.split(['(', '<']).next().unwrap();
+                    handlers.push((name.to_string(), file.clone()));
+                    routed = false;
+                }
+            }
+        }
+
+        assert!(!handlers.is_empty());
+        for (name, file) in handlers {
+            assert!(
+                registered.contains(name.as_str()),
+                "{name} in {file:?} is routed, but isn't registered with \
+                 #[register_endpoint]"
+            );
+        }
+    }
+
+    /// Attributes of actix's route macros
+    const ROUTE_MACROS: [&str; 7] = [
+        "#[get(",
+        "#[post(",
+        "#[put(",
+        "#[patch(",
+        "#[delete(",
+        "#[head(",
+        "#[route(",
+    ];
+
+    /// Every `.rs` file within `dir`.
+    fn source_files(dir: PathBuf) -> Vec<PathBuf> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => files.extend(source_files(path)),
+                false if path.extension() == Some("rs".as_ref()) => {
+                    files.push(path)
+                }
+                false => {}
+            }
+        }
+        files
+    }
+}
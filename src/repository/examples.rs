@@ -0,0 +1,125 @@
+use crate::clock::Clock;
+use crate::database::{ConstraintKind, DbError, DbPools};
+use crate::domain::{Email, PostData};
+use crate::metrics::db_query_timer;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Constraint keeping entries' emails unique
+pub const EMAIL_UNIQUE: &str = "example_email_key";
+
+/// An entry in the `example` table.
+#[derive(Clone, Debug)]
+pub struct Example {
+    pub email: String,
+    pub name: String,
+}
+
+/// Storage for the example endpoints' entries.
+///
+/// Use the `Data<dyn ExampleRepository>` app data in handlers.
+#[async_trait::async_trait]
+pub trait ExampleRepository: Send + Sync {
+    async fn get_by_email(
+        &self,
+        email: &Email,
+    ) -> Result<Option<Example>, DbError>;
+
+    /// Fails with an `EMAIL_UNIQUE` violation if the email already has an
+    /// entry.
+    async fn insert(&self, data: &PostData) -> Result<(), DbError>;
+}
+
+/// Reads from a replica where possible, see `DbPools::read`. Entries are
+/// timestamped by the app's `Clock`.
+pub struct PgExampleRepository {
+    pools: DbPools,
+    clock: Arc<dyn Clock>,
+}
+
+impl PgExampleRepository {
+    pub fn new(pools: DbPools, clock: Arc<dyn Clock>) -> Self {
+        Self { pools, clock }
+    }
+}
+
+#[async_trait::async_trait]
+impl ExampleRepository for PgExampleRepository {
+    #[tracing::instrument(name = "Reading data from database", skip_all)]
+    async fn get_by_email(
+        &self,
+        email: &Email,
+    ) -> Result<Option<Example>, DbError> {
+        let _timer = db_query_timer("read_db");
+        let example = self
+            .pools
+            .read(|pool| async move {
+                sqlx::query_as!(
+                    Example,
+                    r#"
+                    SELECT email, name
+                    FROM example
+                    WHERE email = $1
+                    "#,
+                    email.as_ref()
+                )
+                .fetch_optional(&pool)
+                .await
+            })
+            .await?;
+        Ok(example)
+    }
+
+    #[tracing::instrument(name = "Writing new data to database", skip_all)]
+    async fn insert(&self, data: &PostData) -> Result<(), DbError> {
+        let _timer = db_query_timer("write_db");
+        sqlx::query!(
+            r#"
+            INSERT INTO example (id, email, name, added_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::new_v4(),
+            data.email.as_ref(),
+            data.name.as_ref(),
+            self.clock.now(),
+        )
+        .execute(self.pools.primary())
+        .await?;
+        Ok(())
+    }
+}
+
+/// Entries kept in memory, keyed by email, e.g. for handler unit tests.
+#[derive(Default)]
+pub struct InMemoryExampleRepository(Mutex<HashMap<String, Example>>);
+
+#[async_trait::async_trait]
+impl ExampleRepository for InMemoryExampleRepository {
+    async fn get_by_email(
+        &self,
+        email: &Email,
+    ) -> Result<Option<Example>, DbError> {
+        let examples = self.0.lock().expect("Examples lock poisoned");
+        Ok(examples.get(email.as_ref()).cloned())
+    }
+
+    async fn insert(&self, data: &PostData) -> Result<(), DbError> {
+        let mut examples = self.0.lock().expect("Examples lock poisoned");
+        let email = data.email.as_ref().to_string();
+        if examples.contains_key(&email) {
+            return Err(DbError::constraint_violation(
+                ConstraintKind::Unique,
+                EMAIL_UNIQUE,
+            ));
+        }
+        examples.insert(
+            email.clone(),
+            Example {
+                email,
+                name: data.name.as_ref().to_string(),
+            },
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+mod examples;
+mod users;
+
+pub use examples::*;
+pub use users::*;
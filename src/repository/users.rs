@@ -0,0 +1,180 @@
+use crate::database::{ConstraintKind, DbError};
+use crate::domain::Username;
+use crate::metrics::db_query_timer;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Constraint violated by inserting a user who already exists
+pub const USERNAME_UNIQUE: &str = "users_pkey";
+
+/// Storage for users' credentials.
+///
+/// Use the `Data<dyn UserRepository>` app data in handlers.
+#[async_trait::async_trait]
+pub trait UserRepository: Send + Sync {
+    /// The user's PHC password hash, or `None` for unknown users
+    async fn get_password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<Secret<String>>, DbError>;
+
+    /// Whether the user may use the admin endpoints, `false` for unknown
+    /// users
+    async fn is_admin(&self, username: &str) -> Result<bool, DbError>;
+
+    /// Fails with a violation of `USERNAME_UNIQUE` if the user exists
+    async fn insert(
+        &self,
+        username: &Username,
+        password_hash: Secret<String>,
+        admin: bool,
+    ) -> Result<(), DbError>;
+
+    /// Returns whether the user exists
+    async fn set_password_hash(
+        &self,
+        username: &Username,
+        password_hash: Secret<String>,
+    ) -> Result<bool, DbError>;
+}
+
+pub struct PgUserRepository(pub PgPool);
+
+#[async_trait::async_trait]
+impl UserRepository for PgUserRepository {
+    #[tracing::instrument(name = "Get stored credentials", skip_all)]
+    async fn get_password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<Secret<String>>, DbError> {
+        let _timer = db_query_timer("get_stored_credentials");
+        let password_hash = sqlx::query!(
+            r#"
+            SELECT password
+            FROM users
+            WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_optional(&self.0)
+        .await?
+        .map(|row| Secret::new(row.password));
+        Ok(password_hash)
+    }
+
+    #[tracing::instrument(name = "Check user is admin", skip_all)]
+    async fn is_admin(&self, username: &str) -> Result<bool, DbError> {
+        let _timer = db_query_timer("is_admin");
+        let admin = sqlx::query!(
+            "SELECT admin FROM users WHERE username = $1",
+            username
+        )
+        .fetch_optional(&self.0)
+        .await?
+        .is_some_and(|row| row.admin);
+        Ok(admin)
+    }
+
+    #[tracing::instrument(name = "Insert user", skip_all)]
+    async fn insert(
+        &self,
+        username: &Username,
+        password_hash: Secret<String>,
+        admin: bool,
+    ) -> Result<(), DbError> {
+        let _timer = db_query_timer("insert_user");
+        sqlx::query!(
+            "INSERT INTO users (username, password, admin) VALUES ($1, $2, $3)",
+            username.as_ref(),
+            password_hash.expose_secret(),
+            admin,
+        )
+        .execute(&self.0)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Set password hash", skip_all)]
+    async fn set_password_hash(
+        &self,
+        username: &Username,
+        password_hash: Secret<String>,
+    ) -> Result<bool, DbError> {
+        let _timer = db_query_timer("set_password_hash");
+        let result = sqlx::query!(
+            "UPDATE users SET password = $2 WHERE username = $1",
+            username.as_ref(),
+            password_hash.expose_secret(),
+        )
+        .execute(&self.0)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Users kept in memory, keyed by username, e.g. for handler unit tests.
+#[derive(Default)]
+pub struct InMemoryUserRepository(Mutex<HashMap<String, StoredUser>>);
+
+struct StoredUser {
+    password_hash: Secret<String>,
+    admin: bool,
+}
+
+#[async_trait::async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn get_password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<Secret<String>>, DbError> {
+        let users = self.0.lock().expect("Users lock poisoned");
+        Ok(users.get(username).map(|user| user.password_hash.clone()))
+    }
+
+    async fn is_admin(&self, username: &str) -> Result<bool, DbError> {
+        let users = self.0.lock().expect("Users lock poisoned");
+        Ok(users.get(username).is_some_and(|user| user.admin))
+    }
+
+    async fn insert(
+        &self,
+        username: &Username,
+        password_hash: Secret<String>,
+        admin: bool,
+    ) -> Result<(), DbError> {
+        let mut users = self.0.lock().expect("Users lock poisoned");
+        match users.contains_key(username.as_ref()) {
+            true => Err(DbError::constraint_violation(
+                ConstraintKind::Unique,
+                USERNAME_UNIQUE,
+            )),
+            false => {
+                users.insert(
+                    username.to_string(),
+                    StoredUser {
+                        password_hash,
+                        admin,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn set_password_hash(
+        &self,
+        username: &Username,
+        password_hash: Secret<String>,
+    ) -> Result<bool, DbError> {
+        let mut users = self.0.lock().expect("Users lock poisoned");
+        match users.get_mut(username.as_ref()) {
+            Some(stored) => {
+                stored.password_hash = password_hash;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use crate::audit::{self, AuditQuery};
+use crate::routes::AuditError;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn audit_events(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AuditError> {
+    let events = audit::query(&query, &pool).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
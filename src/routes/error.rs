@@ -1,4 +1,5 @@
 use crate::configuration::HmacSecret;
+use crate::database::{ConstraintKind, DbError};
 use crate::domain::ParseError;
 use actix_web::error::InternalError;
 use actix_web::http::StatusCode;
@@ -17,6 +18,8 @@ pub enum GetError {
     InvalidEmail(#[from] ParseError),
     #[error("{0} not found.")]
     EmailNotFound(String),
+    #[error("{}", database_message(.0))]
+    Database(#[from] DbError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -25,6 +28,10 @@ pub enum GetError {
 pub enum PostError {
     #[error(transparent)]
     InputValidationError(#[from] ParseError),
+    #[error("An entry with this email already exists.")]
+    Conflict(#[source] DbError),
+    #[error("{}", database_message(.0))]
+    Database(#[from] DbError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -33,17 +40,62 @@ pub enum PostError {
 pub enum AuthError {
     #[error("Invalid username and/or password.")]
     InvalidCredentials(#[source] anyhow::Error),
+    #[error("{0} is not an admin.")]
+    Forbidden(String),
+    #[error("{}", database_message(.0))]
+    Database(#[from] DbError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
 pub type LoginError = InternalError<AuthError>;
 
+#[derive(thiserror::Error)]
+pub enum AuditError {
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("{}", database_message(.0))]
+    Database(#[from] DbError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(thiserror::Error)]
+pub enum LogFilterError {
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Invalid log filter directive: {0}")]
+    InvalidDirective(#[from] tracing_subscriber::filter::ParseError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("Invalid Idempotency-Key header, it's {0}.")]
+    InvalidKey(String),
+    #[error("A request with this Idempotency-Key is already in progress.")]
+    InProgress,
+    #[error("This Idempotency-Key was already used for a different request.")]
+    KeyReused,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(thiserror::Error)]
+pub enum TransactionError {
+    #[error("The request conflicted with concurrent requests, try again.")]
+    Conflict,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
 impl ResponseError for GetError {
     fn status_code(&self) -> StatusCode {
         match self {
             GetError::InvalidEmail(_) => StatusCode::BAD_REQUEST,
             GetError::EmailNotFound(_) => StatusCode::NOT_FOUND,
+            GetError::Database(e) => database_status_code(e),
             GetError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -59,6 +111,8 @@ impl ResponseError for PostError {
     fn status_code(&self) -> StatusCode {
         match self {
             PostError::InputValidationError(_) => StatusCode::BAD_REQUEST,
+            PostError::Conflict(_) => StatusCode::CONFLICT,
+            PostError::Database(e) => database_status_code(e),
             PostError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -74,6 +128,8 @@ impl ResponseError for AuthError {
     fn status_code(&self) -> StatusCode {
         match self {
             AuthError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AuthError::Database(e) => database_status_code(e),
             AuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -85,6 +141,104 @@ impl std::fmt::Debug for AuthError {
     }
 }
 
+impl ResponseError for AuditError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuditError::AuthError(e) => e.status_code(),
+            AuditError::Database(e) => database_status_code(e),
+            AuditError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Debug for AuditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LogFilterError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LogFilterError::AuthError(e) => e.status_code(),
+            LogFilterError::InvalidDirective(_) => StatusCode::BAD_REQUEST,
+            LogFilterError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for LogFilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for IdempotencyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IdempotencyError::InvalidKey(_) => StatusCode::BAD_REQUEST,
+            IdempotencyError::InProgress => StatusCode::CONFLICT,
+            IdempotencyError::KeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            IdempotencyError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for IdempotencyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TransactionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TransactionError::Conflict => StatusCode::CONFLICT,
+            TransactionError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for TransactionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Client-facing message for a database error that a handler didn't map
+/// itself, so constraint names and Postgres' messages are only logged.
+fn database_message(e: &DbError) -> &'static str {
+    match e.kind() {
+        Some(ConstraintKind::Unique) => "A conflicting record already exists.",
+        Some(
+            ConstraintKind::ForeignKey
+            | ConstraintKind::NotNull
+            | ConstraintKind::Check,
+        ) => "The record is missing or has invalid fields.",
+        None => "An unexpected database error occurred.",
+    }
+}
+
+/// Status for a database error that a handler didn't map itself, shared by
+/// every handler error's `Database` variant.
+fn database_status_code(e: &DbError) -> StatusCode {
+    match e.kind() {
+        Some(ConstraintKind::Unique) => StatusCode::CONFLICT,
+        Some(
+            ConstraintKind::ForeignKey
+            | ConstraintKind::NotNull
+            | ConstraintKind::Check,
+        ) => StatusCode::UNPROCESSABLE_ENTITY,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 pub fn error_msg_to_query_string(error_msg: &String) -> String {
     format!("error={}", urlencoding::Encoded::new(error_msg))
 }
@@ -139,3 +293,35 @@ fn error_chain_fmt(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_errors_are_mapped_by_every_handler_error() {
+        let violation =
+            |kind| DbError::constraint_violation(kind, "users_pkey");
+
+        let conflict = AuthError::from(violation(ConstraintKind::Unique));
+        let invalid = AuditError::from(violation(ConstraintKind::Check));
+        let missing = PostError::from(violation(ConstraintKind::ForeignKey));
+        let other = GetError::from(DbError::from(sqlx::Error::PoolTimedOut));
+
+        assert_eq!(StatusCode::CONFLICT, conflict.status_code());
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, invalid.status_code());
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, missing.status_code());
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, other.status_code());
+    }
+
+    #[test]
+    fn database_errors_are_only_detailed_in_the_error_chain() {
+        let error = PostError::from(DbError::constraint_violation(
+            ConstraintKind::Unique,
+            "users_pkey",
+        ));
+
+        assert_eq!("A conflicting record already exists.", error.to_string());
+        assert!(format!("{error:?}").contains("users_pkey"));
+    }
+}
@@ -1,8 +1,7 @@
 use crate::domain::{Email, Parseable};
+use crate::repository::ExampleRepository;
 use crate::routes::GetError;
 use actix_web::{web, HttpResponse};
-use anyhow::Context;
-use sqlx::PgPool;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct ExampleGetResponse {
@@ -10,50 +9,64 @@ pub struct ExampleGetResponse {
     pub name: String,
 }
 
-struct Record {
-    name: String,
-    email: String,
-}
-
 /// Get the data associated with an email address, or return 400
 pub async fn example_get(
     email: web::Path<String>,
-    pool: web::Data<PgPool>,
+    examples: web::Data<dyn ExampleRepository>,
 ) -> Result<HttpResponse, GetError> {
     let email = Email::parse(email.into_inner())?;
-    let response = read_db(&email, &pool)
-        .await
-        .context("Failed to read database.")?;
+    let response = examples.get_by_email(&email).await?;
     match response {
         None => Err(GetError::EmailNotFound(email.as_ref().to_string())),
-        Some(record) => Ok(HttpResponse::Ok().json(ExampleGetResponse {
-            name: record.name,
-            email: record.email,
+        Some(example) => Ok(HttpResponse::Ok().json(ExampleGetResponse {
+            name: example.name,
+            email: example.email,
         })),
     }
 }
 
-#[tracing::instrument(name = "Reading data from database", skip(email, pool))]
-async fn read_db(
-    email: &Email,
-    pool: &PgPool,
-) -> Result<Option<Record>, sqlx::Error> {
-    let record = sqlx::query!(
-        r#"
-        SELECT *
-        FROM example
-        WHERE email = $1
-        "#,
-        &email.as_ref()
-    )
-    .fetch_optional(pool)
-    .await?;
-
-    Ok(match record {
-        Some(r) => Some(Record {
-            email: r.email,
-            name: r.name,
-        }),
-        None => None,
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Name, PostData};
+    use crate::repository::InMemoryExampleRepository;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use std::sync::Arc;
+
+    fn examples() -> web::Data<dyn ExampleRepository> {
+        web::Data::from(Arc::new(InMemoryExampleRepository::default())
+            as Arc<dyn ExampleRepository>)
+    }
+
+    #[actix_web::test]
+    async fn stored_examples_are_returned() {
+        let examples = examples();
+        let data = PostData {
+            name: Name::parse("Barry".to_string()).unwrap(),
+            email: Email::parse("barry@barry.com".to_string()).unwrap(),
+        };
+        examples.insert(&data).await.unwrap();
+
+        let response = example_get(
+            web::Path::from("barry@barry.com".to_string()),
+            examples,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[actix_web::test]
+    async fn missing_examples_are_not_found() {
+        let error = example_get(
+            web::Path::from("barry@barry.com".to_string()),
+            examples(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(StatusCode::NOT_FOUND, error.status_code());
+    }
 }
@@ -1,11 +1,9 @@
 use crate::domain::Parseable;
 use crate::domain::{self, ParseError, PostData};
+use crate::repository::{ExampleRepository, EMAIL_UNIQUE};
 use crate::routes::PostError;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
-use chrono::Utc;
-use sqlx::PgPool;
-use uuid::Uuid;
 
 //TODO:
 //  - error chaining?
@@ -18,35 +16,21 @@ pub struct PostExampleForm {
 
 pub async fn example_post(
     form: web::Form<PostExampleForm>,
-    pool: web::Data<PgPool>,
+    examples: web::Data<dyn ExampleRepository>,
 ) -> Result<HttpResponse, PostError> {
     let post_data = form
         .0
         .try_into()
         .context("Failed to parse data from form.")?;
-    write_db(&post_data, &pool)
-        .await
-        .context("Failed to write to db.")?;
+    examples.insert(&post_data).await.map_err(|e| {
+        match e.constraint() == Some(EMAIL_UNIQUE) {
+            true => PostError::Conflict(e),
+            false => PostError::Database(e),
+        }
+    })?;
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Writing new data to database", skip(form, pool))]
-async fn write_db(form: &PostData, pool: &PgPool) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"
-        INSERT INTO example (id, email, name, added_at)
-        VALUES ($1, $2, $3, $4)
-        "#,
-        Uuid::new_v4(),
-        form.email.as_ref(),
-        form.name.as_ref(),
-        Utc::now(),
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
-}
-
 impl TryFrom<PostExampleForm> for PostData {
     type Error = ParseError;
 
@@ -56,3 +40,36 @@ impl TryFrom<PostExampleForm> for PostData {
         Ok(PostData { name, email })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryExampleRepository;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use std::sync::Arc;
+
+    fn form(email: &str) -> web::Form<PostExampleForm> {
+        web::Form(PostExampleForm {
+            name: "Barry".into(),
+            email: email.into(),
+        })
+    }
+
+    #[actix_web::test]
+    async fn existing_emails_conflict() {
+        let examples: web::Data<dyn ExampleRepository> =
+            web::Data::from(Arc::new(InMemoryExampleRepository::default())
+                as Arc<dyn ExampleRepository>);
+
+        let first = example_post(form("barry@barry.com"), examples.clone())
+            .await
+            .unwrap();
+        let second = example_post(form("barry@barry.com"), examples)
+            .await
+            .unwrap_err();
+
+        assert_eq!(StatusCode::OK, first.status());
+        assert_eq!(StatusCode::CONFLICT, second.status_code());
+    }
+}
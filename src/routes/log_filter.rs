@@ -0,0 +1,30 @@
+use crate::routes::LogFilterError;
+use crate::telemetry::LogFilter;
+use actix_web::{web, HttpResponse};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+#[derive(serde::Deserialize)]
+pub struct LogFilterBody {
+    /// `EnvFilter` directive, e.g. `info,actix_web_template=debug`
+    pub directive: String,
+    /// Restore the current filter after this many seconds
+    pub revert_after_secs: Option<u64>,
+}
+
+pub async fn get_log_filter(
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, LogFilterError> {
+    let status = log_filter.status()?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+pub async fn set_log_filter(
+    body: web::Json<LogFilterBody>,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, LogFilterError> {
+    let filter = EnvFilter::try_new(&body.directive)?;
+    let revert_after = body.revert_after_secs.map(Duration::from_secs);
+    let status = log_filter.set(filter, revert_after)?;
+    Ok(HttpResponse::Ok().json(status))
+}
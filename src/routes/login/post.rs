@@ -1,11 +1,11 @@
-use crate::auth::validate_credentials;
+use crate::auth::{validate_credentials, AuthenticatedUser};
 use crate::domain::{Credentials, Parseable, Password, Username};
+use crate::repository::UserRepository;
 use crate::routes::AuthError;
 use actix_web::http::header::LOCATION;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use secrecy::Secret;
 use serde::Deserialize;
-use sqlx::PgPool;
 
 #[derive(Deserialize)]
 pub struct FormData {
@@ -13,12 +13,11 @@ pub struct FormData {
     pub password: Secret<String>,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum LoginError {}
-
+/// Successful logins are recorded in the request's `AuthenticatedUser`.
 pub async fn login(
+    request: HttpRequest,
     form: web::Form<FormData>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
 ) -> Result<HttpResponse, AuthError> {
     //TODO: better error handling?
     let credentials = Credentials {
@@ -29,10 +28,9 @@ pub async fn login(
             AuthError::InvalidCredentials(anyhow::Error::new(e))
         })?,
     };
-    match validate_credentials(credentials, &pool).await {
-        Ok(_) => Ok(HttpResponse::SeeOther()
-            .insert_header((LOCATION, "/"))
-            .finish()),
-        Err(e) => Err(e),
-    }
+    let username = validate_credentials(credentials, users.get_ref()).await?;
+    request.extensions_mut().insert(AuthenticatedUser(username));
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/"))
+        .finish())
 }
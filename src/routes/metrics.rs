@@ -0,0 +1,14 @@
+use crate::metrics;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+/// Prometheus metrics in the text exposition format
+pub async fn metrics(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let metrics = metrics::render(&pool).map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics))
+}
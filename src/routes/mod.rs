@@ -1,13 +1,21 @@
+mod audit_events;
 mod error;
 mod example_get;
 mod example_post;
 mod health_check;
 mod home;
+mod log_filter;
 pub mod login;
+mod metrics;
+mod ready;
 
+pub use audit_events::*;
 pub use error::*;
 pub use example_get::*;
 pub use example_post::*;
 pub use health_check::*;
 pub use home::*;
+pub use log_filter::*;
+pub use metrics::*;
+pub use ready::*;
 // pub use login::*;
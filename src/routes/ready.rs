@@ -0,0 +1,12 @@
+use crate::readiness::ReadinessChecks;
+use actix_web::{web, HttpResponse};
+
+/// Response 200 if every critical dependency is available, otherwise 503.
+/// The body reports the status and latency of each check.
+pub async fn ready(checks: web::Data<ReadinessChecks>) -> HttpResponse {
+    let report = checks.run().await;
+    match report.ready {
+        true => HttpResponse::Ok().json(report),
+        false => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
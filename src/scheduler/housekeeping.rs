@@ -0,0 +1,79 @@
+use crate::jobs::JobContext;
+use crate::scheduler::ScheduledTask;
+use anyhow::Context;
+
+/// Deletes `example` rows added longer ago than `max_age`.
+pub struct PurgeExamples {
+    pub max_age: chrono::Duration,
+}
+
+#[async_trait::async_trait]
+impl ScheduledTask for PurgeExamples {
+    fn name(&self) -> &'static str {
+        "purge_examples"
+    }
+
+    async fn run(&self, context: &JobContext) -> Result<(), anyhow::Error> {
+        let purged = sqlx::query!(
+            "DELETE FROM example WHERE added_at < $1",
+            context.clock.now() - self.max_age,
+        )
+        .execute(&context.db_pool)
+        .await
+        .context("Failed to delete old examples")?
+        .rows_affected();
+        tracing::info!(purged, "Purged old examples");
+        Ok(())
+    }
+}
+
+/// Deletes dead-lettered jobs last attempted longer ago than `max_age`.
+pub struct PurgeDeadJobs {
+    pub max_age: chrono::Duration,
+}
+
+#[async_trait::async_trait]
+impl ScheduledTask for PurgeDeadJobs {
+    fn name(&self) -> &'static str {
+        "purge_dead_jobs"
+    }
+
+    async fn run(&self, context: &JobContext) -> Result<(), anyhow::Error> {
+        let purged = sqlx::query!(
+            "DELETE FROM jobs WHERE status = 'dead' AND run_at < $1",
+            context.clock.now() - self.max_age,
+        )
+        .execute(&context.db_pool)
+        .await
+        .context("Failed to delete dead jobs")?
+        .rows_affected();
+        tracing::info!(purged, "Purged dead jobs");
+        Ok(())
+    }
+}
+
+/// Deletes saved idempotent responses older than `max_age`, after which
+/// their keys can be reused.
+pub struct PurgeIdempotencyKeys {
+    pub max_age: chrono::Duration,
+}
+
+#[async_trait::async_trait]
+impl ScheduledTask for PurgeIdempotencyKeys {
+    fn name(&self) -> &'static str {
+        "purge_idempotency_keys"
+    }
+
+    async fn run(&self, context: &JobContext) -> Result<(), anyhow::Error> {
+        let purged = sqlx::query!(
+            "DELETE FROM idempotency WHERE created_at < $1",
+            context.clock.now() - self.max_age,
+        )
+        .execute(&context.db_pool)
+        .await
+        .context("Failed to delete old idempotency keys")?
+        .rows_affected();
+        tracing::info!(purged, "Purged old idempotency keys");
+        Ok(())
+    }
+}
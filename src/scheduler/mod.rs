@@ -0,0 +1,211 @@
+mod housekeeping;
+
+pub use housekeeping::*;
+
+use crate::configuration::CronSchedule;
+use crate::jobs::JobContext;
+use crate::shutdown::ShutdownHook;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Work run periodically, e.g. housekeeping.
+///
+/// Implement this for new tasks, register them with
+/// `ApplicationBuilder::scheduled_task` and give them a schedule under
+/// `scheduler.tasks` in the settings.
+#[async_trait::async_trait]
+pub trait ScheduledTask: Send + Sync {
+    /// Names the task's schedule in the settings, and its advisory lock
+    fn name(&self) -> &'static str;
+
+    async fn run(&self, context: &JobContext) -> Result<(), anyhow::Error>;
+}
+
+/// Runs each registered task on its schedule, spawned alongside the server.
+///
+/// Every replica schedules every task, but each scheduled run is only made
+/// by the replica which takes the task's advisory lock first.
+#[derive(Clone)]
+pub struct Scheduler {
+    schedules: BTreeMap<String, CronSchedule>,
+    context: JobContext,
+    tasks: Vec<Arc<dyn ScheduledTask>>,
+    stop: Arc<watch::Sender<bool>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        schedules: BTreeMap<String, CronSchedule>,
+        context: JobContext,
+        tasks: Vec<Arc<dyn ScheduledTask>>,
+    ) -> Self {
+        Self {
+            schedules,
+            context,
+            tasks,
+            stop: Arc::new(watch::channel(false).0),
+            handles: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Spawn a loop for each scheduled task, which runs until this is shut
+    /// down.
+    pub fn spawn(&self) {
+        for name in self.schedules.keys() {
+            if !self.tasks.iter().any(|task| task.name() == name) {
+                tracing::warn!(task = %name, "Scheduled task isn't registered");
+            }
+        }
+        let mut handles = self.handles.lock().expect("Scheduler lock poisoned");
+        for task in &self.tasks {
+            match self.schedules.get(task.name()) {
+                Some(schedule) => {
+                    handles.push(tokio::spawn(self.clone().run_on_schedule(
+                        task.clone(),
+                        schedule.clone(),
+                        self.stop.subscribe(),
+                    )))
+                }
+                None => {
+                    tracing::info!(task = task.name(), "Task isn't scheduled")
+                }
+            }
+        }
+    }
+
+    async fn run_on_schedule(
+        self,
+        task: Arc<dyn ScheduledTask>,
+        schedule: CronSchedule,
+        mut stop: watch::Receiver<bool>,
+    ) {
+        while !*stop.borrow() {
+            let now = self.context.clock.now();
+            let scheduled_at = match schedule.0.after(&now).next() {
+                Some(scheduled_at) => scheduled_at,
+                // The schedule has no more runs
+                None => return,
+            };
+            let delay = (scheduled_at - now).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = stop.changed() => return,
+            }
+            if let Err(e) =
+                run_scheduled_task(task.as_ref(), scheduled_at, &self.context)
+                    .await
+            {
+                tracing::error!(
+                    task = task.name(),
+                    error = ?e,
+                    "Failed to run scheduled task"
+                );
+            }
+        }
+    }
+}
+
+/// Stops scheduling tasks, waiting for running tasks to finish.
+#[async_trait::async_trait]
+impl ShutdownHook for Scheduler {
+    fn name(&self) -> &'static str {
+        "scheduler"
+    }
+
+    async fn shutdown(&self) -> Result<(), anyhow::Error> {
+        self.stop.send_replace(true);
+        let handles = std::mem::take(
+            &mut *self.handles.lock().expect("Scheduler lock poisoned"),
+        );
+        for handle in handles {
+            handle.await.context("Scheduled task loop failed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Make the run of `task` scheduled at `scheduled_at`, returning whether it
+/// ran here.
+///
+/// Runs are skipped while another replica holds the task's advisory lock, or
+/// once the run has already been made. A failing task is logged and
+/// recorded, but isn't retried until its next scheduled run.
+#[tracing::instrument(
+    name = "Run scheduled task",
+    skip_all,
+    fields(task = task.name(), %scheduled_at)
+)]
+pub async fn run_scheduled_task(
+    task: &dyn ScheduledTask,
+    scheduled_at: DateTime<Utc>,
+    context: &JobContext,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = context
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to begin transaction")?;
+    // Held until the transaction ends
+    let locked = sqlx::query!(
+        r#"SELECT pg_try_advisory_xact_lock(hashtext($1)) AS "locked!""#,
+        format!("scheduled_task:{}", task.name()),
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to take advisory lock")?
+    .locked;
+    if !locked {
+        tracing::info!("Task is running on another replica");
+        return Ok(false);
+    }
+    let last_run = sqlx::query!(
+        r#"
+        SELECT last_scheduled_at
+        FROM scheduled_task_runs
+        WHERE name = $1
+        "#,
+        task.name(),
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch last run")?;
+    if let Some(last_run) = last_run {
+        if last_run.last_scheduled_at >= scheduled_at {
+            tracing::info!("Task has already run on another replica");
+            return Ok(false);
+        }
+    }
+
+    let result = task.run(context).await;
+    if let Err(e) = &result {
+        tracing::error!(error = ?e, "Scheduled task failed");
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO scheduled_task_runs
+            (name, last_scheduled_at, last_finished_at, last_error)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO UPDATE
+        SET last_scheduled_at = EXCLUDED.last_scheduled_at,
+            last_finished_at = EXCLUDED.last_finished_at,
+            last_error = EXCLUDED.last_error
+        "#,
+        task.name(),
+        scheduled_at,
+        context.clock.now(),
+        result.err().map(|e| format!("{e:?}")),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to record run")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit scheduled task transaction")?;
+    Ok(true)
+}
@@ -0,0 +1,264 @@
+use crate::configuration::ShutdownSettings;
+use crate::readiness::{ReadinessCheck, RegisteredCheck};
+use actix_web::dev::Server;
+use actix_web::rt::time::{sleep, timeout};
+use anyhow::anyhow;
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Hooks slower than this are abandoned
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cleanup to run once the server has stopped, e.g. flushing a background
+/// task's work.
+///
+/// Implement this for new background tasks and add them with
+/// `Shutdown::register`.
+#[async_trait::async_trait]
+pub trait ShutdownHook: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn shutdown(&self) -> Result<(), anyhow::Error>;
+}
+
+/// Coordinates graceful shutdown of the server and its background tasks.
+///
+/// Once SIGTERM or SIGINT is received (or `trigger` is called), `/ready`
+/// starts failing, the server stops accepting connections after the drain
+/// delay, in-flight requests are given until the timeout to complete, and
+/// then every hook runs in the order registered.
+#[derive(Clone)]
+pub struct Shutdown {
+    settings: ShutdownSettings,
+    handle_signals: bool,
+    draining: Arc<AtomicBool>,
+    triggered: Arc<Notify>,
+    hooks: Arc<Mutex<Vec<Arc<dyn ShutdownHook>>>>,
+}
+
+impl Shutdown {
+    pub fn new(settings: ShutdownSettings) -> Self {
+        Self {
+            settings,
+            handle_signals: true,
+            draining: Arc::new(AtomicBool::new(false)),
+            triggered: Arc::new(Notify::new()),
+            hooks: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Only shut down when `trigger` is called, e.g. in tests so Ctrl+C still
+    /// stops the test run.
+    pub fn without_signals(mut self) -> Self {
+        self.handle_signals = false;
+        self
+    }
+
+    pub fn register(&self, hook: impl ShutdownHook + 'static) {
+        self.hooks
+            .lock()
+            .expect("Shutdown hooks lock poisoned")
+            .push(Arc::new(hook));
+    }
+
+    /// Start shutting down as if a signal had been received.
+    pub fn trigger(&self) {
+        self.triggered.notify_one();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Time in-flight requests have to complete, see
+    /// `HttpServer::shutdown_timeout`.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.settings.timeout_secs)
+    }
+
+    /// Run the server until it's shut down, then run the shutdown hooks.
+    ///
+    /// The server must be built with `HttpServer::disable_signals`, otherwise
+    /// actix stops it immediately on a signal.
+    pub async fn run_until_stopped(
+        self,
+        server: Server,
+    ) -> Result<(), std::io::Error> {
+        let handle = server.handle();
+        tokio::pin!(server);
+
+        tokio::select! {
+            // Stopped without a signal, e.g. failed
+            result = &mut server => result?,
+            signal = self.wait_for_signal() => {
+                tracing::info!(signal, "Shutting down, draining requests");
+                self.draining.store(true, Ordering::SeqCst);
+                sleep(Duration::from_secs(self.settings.drain_delay_secs))
+                    .await;
+                // The server only handles the stop command while polled
+                let (_, result) = tokio::join!(handle.stop(true), &mut server);
+                result?;
+            }
+        }
+
+        self.run_hooks().await;
+        tracing::info!("Shutdown complete");
+        Ok(())
+    }
+
+    async fn wait_for_signal(&self) -> &'static str {
+        if !self.handle_signals {
+            self.triggered.notified().await;
+            return "trigger";
+        }
+
+        #[cfg(unix)]
+        let terminate = async {
+            tokio::signal::unix::signal(
+                tokio::signal::unix::SignalKind::terminate(),
+            )
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate => "SIGTERM",
+            _ = self.triggered.notified() => "trigger",
+        }
+    }
+
+    /// Failing hooks are logged, but don't stop later hooks from running.
+    async fn run_hooks(&self) {
+        let hooks = self
+            .hooks
+            .lock()
+            .expect("Shutdown hooks lock poisoned")
+            .clone();
+        for hook in hooks {
+            let result =
+                timeout(HOOK_TIMEOUT, hook.shutdown()).await.unwrap_or_else(
+                    |_| Err(anyhow!("Timed out after {HOOK_TIMEOUT:?}")),
+                );
+            if let Err(e) = result {
+                tracing::error!(hook = hook.name(), error = ?e, "Shutdown hook failed");
+            }
+        }
+    }
+}
+
+/// Fails once shutdown starts, so load balancers stop routing requests here
+/// while in-flight requests drain.
+pub struct DrainingCheck(pub Shutdown);
+
+inventory::submit!(RegisteredCheck(|deps| {
+    Arc::new(DrainingCheck(deps.shutdown.clone()))
+}));
+
+#[async_trait::async_trait]
+impl ReadinessCheck for DrainingCheck {
+    fn name(&self) -> &'static str {
+        "shutdown"
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        match self.0.is_draining() {
+            true => Err(anyhow!("Shutting down")),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Closes the Postgres pool, waiting for connections to be returned.
+pub struct ClosePool(pub PgPool);
+
+#[async_trait::async_trait]
+impl ShutdownHook for ClosePool {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn shutdown(&self) -> Result<(), anyhow::Error> {
+        self.0.close().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpServer};
+    use std::net::TcpListener;
+
+    struct RecordingHook {
+        name: &'static str,
+        ran: Arc<Mutex<Vec<&'static str>>>,
+        healthy: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl ShutdownHook for RecordingHook {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn shutdown(&self) -> Result<(), anyhow::Error> {
+            self.ran.lock().unwrap().push(self.name);
+            match self.healthy {
+                true => Ok(()),
+                false => Err(anyhow!("Hook failed")),
+            }
+        }
+    }
+
+    fn shutdown() -> Shutdown {
+        Shutdown::new(ShutdownSettings {
+            drain_delay_secs: 0,
+            timeout_secs: 1,
+        })
+        .without_signals()
+    }
+
+    #[actix_web::test]
+    async fn readiness_fails_while_draining() {
+        let shutdown = shutdown();
+        let check = DrainingCheck(shutdown.clone());
+        assert!(check.check().await.is_ok());
+
+        shutdown.draining.store(true, Ordering::SeqCst);
+
+        assert!(check.check().await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn hooks_run_in_order_after_server_stops() {
+        let shutdown = shutdown();
+        let ran = Arc::new(Mutex::new(vec![]));
+        for (name, healthy) in [("first", false), ("second", true)] {
+            shutdown.register(RecordingHook {
+                name,
+                ran: ran.clone(),
+                healthy,
+            });
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = HttpServer::new(App::new)
+            .disable_signals()
+            .listen(listener)
+            .unwrap()
+            .run();
+
+        shutdown.trigger();
+        shutdown.clone().run_until_stopped(server).await.unwrap();
+
+        assert!(shutdown.is_draining());
+        // A failing hook doesn't prevent later hooks from running
+        assert_eq!(vec!["first", "second"], *ran.lock().unwrap());
+    }
+}
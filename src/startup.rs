@@ -1,49 +1,380 @@
-use crate::configuration::HmacSecret;
-use crate::endpoint::{
-    example_auth, example_get, example_post, health_check, home, login,
-    login_form,
+use crate::audit::AuditLog;
+use crate::clock::{Clock, SystemClock};
+use crate::configuration::{
+    DatabaseSettings, HmacSecret, ReplicaSettings, Settings,
 };
+use crate::database::{
+    DbPools, ReadYourWrites, ReplicaHealthChecks, Transactional,
+};
+use crate::email::{EmailSender, LogEmailSender};
+use crate::idempotency::Idempotency;
+use crate::jobs::{Job, JobContext, JobHandlers, SendEmail, Workers};
+use crate::metrics::RequestMetrics;
+use crate::migrations;
+use crate::readiness::{CheckDependencies, ReadinessChecks};
+use crate::registry;
+use crate::repository::{
+    ExampleRepository, PgExampleRepository, PgUserRepository, UserRepository,
+};
+use crate::scheduler::{
+    PurgeDeadJobs, PurgeExamples, PurgeIdempotencyKeys, ScheduledTask,
+    Scheduler,
+};
+use crate::shutdown::{ClosePool, Shutdown};
+use crate::telemetry::{self, LogFilter, Redactor};
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
-use actix_web::web::Data;
+use actix_web::web::{Data, FormConfig, JsonConfig, PayloadConfig};
 use actix_web::{App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use secrecy::ExposeSecret;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
-/// Run the server using the provided TCP Listener
-pub fn run(
-    listener: TcpListener,
+/// A server bound to its port, ready to be run.
+pub struct Application {
+    port: u16,
+    server: Server,
+    shutdown: Shutdown,
+    workers: Workers,
+    scheduler: Scheduler,
+    replica_health_checks: ReplicaHealthChecks,
     db_pool: PgPool,
-    hmac_secret: HmacSecret,
-) -> Result<Server, std::io::Error> {
-    let connection_pool = Data::new(db_pool);
-    let message_store = CookieMessageStore::builder(Key::from(
-        hmac_secret.0.expose_secret().as_bytes(),
-    ))
-    .build();
-    let message_framework =
-        FlashMessagesFramework::builder(message_store).build();
-    // Build the app
-    let server = HttpServer::new(move || {
-        //TODO: add service in configuration fn instead
-        App::new()
-            .wrap(message_framework.clone())
-            .wrap(TracingLogger::default())
-            .service(health_check)
-            .service(example_get)
-            .service(example_post)
-            .service(example_auth)
-            .service(home)
-            .service(login_form)
-            .service(login)
-            .app_data(connection_pool.clone())
-            .app_data(Data::new(hmac_secret.clone()))
-    })
-    .listen(listener)?
-    .run();
-    Ok(server)
+    migrate_on_startup: bool,
+}
+
+impl Application {
+    /// Build the app from `settings`, using `log_filter` for the subscriber
+    /// it should change at runtime.
+    pub fn builder(
+        settings: Settings,
+        log_filter: LogFilter,
+    ) -> ApplicationBuilder {
+        let retention = &settings.scheduler.retention;
+        let scheduled_tasks: Vec<Arc<dyn ScheduledTask>> = vec![
+            Arc::new(PurgeExamples {
+                max_age: chrono::Duration::days(retention.examples_days.into()),
+            }),
+            Arc::new(PurgeDeadJobs {
+                max_age: chrono::Duration::days(
+                    retention.dead_jobs_days.into(),
+                ),
+            }),
+            Arc::new(PurgeIdempotencyKeys {
+                max_age: chrono::Duration::days(
+                    retention.idempotency_keys_days.into(),
+                ),
+            }),
+        ];
+        ApplicationBuilder {
+            settings,
+            log_filter,
+            db_pool: None,
+            replica_pools: None,
+            clock: Arc::new(SystemClock),
+            email_sender: Arc::new(LogEmailSender),
+            example_repository: None,
+            user_repository: None,
+            job_handlers: JobHandlers::new().register::<SendEmail>(),
+            scheduled_tasks,
+            handle_signals: true,
+        }
+    }
+
+    /// The port bound, which is only known after building when the settings
+    /// use port 0.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Run the server, job workers and scheduler until they're shut down
+    /// gracefully, see `Shutdown::run_until_stopped`.
+    ///
+    /// Pending migrations are applied first if the settings ask for it.
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        if self.migrate_on_startup {
+            migrations::run(&self.db_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+        self.workers.spawn();
+        self.scheduler.spawn();
+        self.replica_health_checks.spawn();
+        self.shutdown.run_until_stopped(self.server).await
+    }
+}
+
+/// Builds an `Application`, with components which can be overridden, e.g. in
+/// tests.
+pub struct ApplicationBuilder {
+    settings: Settings,
+    log_filter: LogFilter,
+    db_pool: Option<PgPool>,
+    replica_pools: Option<Vec<PgPool>>,
+    clock: Arc<dyn Clock>,
+    email_sender: Arc<dyn EmailSender>,
+    example_repository: Option<Arc<dyn ExampleRepository>>,
+    user_repository: Option<Arc<dyn UserRepository>>,
+    job_handlers: JobHandlers,
+    scheduled_tasks: Vec<Arc<dyn ScheduledTask>>,
+    handle_signals: bool,
+}
+
+impl ApplicationBuilder {
+    /// Defaults to a lazily connected pool for the database settings.
+    pub fn db_pool(mut self, db_pool: PgPool) -> Self {
+        self.db_pool = Some(db_pool);
+        self
+    }
+
+    /// Pools for read replicas, defaulting to lazily connected pools for the
+    /// replication settings.
+    pub fn replica_pools(mut self, replica_pools: Vec<PgPool>) -> Self {
+        self.replica_pools = Some(replica_pools);
+        self
+    }
+
+    /// Defaults to `SystemClock`.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Defaults to `LogEmailSender`.
+    pub fn email_sender(
+        mut self,
+        email_sender: impl EmailSender + 'static,
+    ) -> Self {
+        self.email_sender = Arc::new(email_sender);
+        self
+    }
+
+    /// Defaults to `PgExampleRepository` using the pool, replicas and clock.
+    pub fn example_repository(
+        mut self,
+        examples: impl ExampleRepository + 'static,
+    ) -> Self {
+        self.example_repository = Some(Arc::new(examples));
+        self
+    }
+
+    /// Defaults to `PgUserRepository` using the pool.
+    pub fn user_repository(
+        mut self,
+        users: impl UserRepository + 'static,
+    ) -> Self {
+        self.user_repository = Some(Arc::new(users));
+        self
+    }
+
+    /// Run jobs of kind `J` in the workers.
+    pub fn job<J: Job>(mut self) -> Self {
+        self.job_handlers = self.job_handlers.register::<J>();
+        self
+    }
+
+    /// Run `task` on its schedule from the settings.
+    pub fn scheduled_task(
+        mut self,
+        task: impl ScheduledTask + 'static,
+    ) -> Self {
+        self.scheduled_tasks.push(Arc::new(task));
+        self
+    }
+
+    /// See `Shutdown::without_signals`.
+    pub fn without_signals(mut self) -> Self {
+        self.handle_signals = false;
+        self
+    }
+
+    /// Bind to the configured address and build the server.
+    pub fn build(self) -> Result<Application, std::io::Error> {
+        let listener = TcpListener::bind(self.settings.get_address())?;
+        let port = listener.local_addr()?.port();
+        let db_pool = self
+            .db_pool
+            .unwrap_or_else(|| get_connection_pool(&self.settings.database));
+        let replication = &self.settings.database.replication;
+        let replica_pools = self.replica_pools.unwrap_or_else(|| {
+            replication
+                .replicas
+                .iter()
+                .map(|replica| {
+                    get_replica_pool(&self.settings.database, replica)
+                })
+                .collect()
+        });
+        let db_pools = DbPools::new(db_pool.clone(), replica_pools);
+        let replica_health_checks = ReplicaHealthChecks::new(
+            db_pools.clone(),
+            Duration::from_secs(replication.health_check_interval_secs),
+        );
+        let read_your_writes_window =
+            Duration::from_secs(replication.read_your_writes_secs);
+        let shutdown = match self.handle_signals {
+            true => Shutdown::new(self.settings.shutdown.clone()),
+            false => {
+                Shutdown::new(self.settings.shutdown.clone()).without_signals()
+            }
+        };
+
+        let readiness_checks =
+            Data::new(ReadinessChecks::registered(&CheckDependencies {
+                db_pool: db_pool.clone(),
+                shutdown: shutdown.clone(),
+            }));
+        let job_context = JobContext {
+            db_pool: db_pool.clone(),
+            clock: self.clock.clone(),
+            email_sender: self.email_sender.clone(),
+        };
+        let workers = Workers::new(
+            self.settings.jobs.clone(),
+            job_context.clone(),
+            self.job_handlers,
+        );
+        let scheduler = Scheduler::new(
+            self.settings.scheduler.tasks.clone(),
+            job_context,
+            self.scheduled_tasks,
+        );
+        // Workers and tasks need the pool until they finish
+        shutdown.register(workers.clone());
+        shutdown.register(scheduler.clone());
+        shutdown.register(replica_health_checks.clone());
+        shutdown.register(ClosePool(db_pool.clone()));
+        // Audit event payloads are redacted in the same way as the logs
+        let redactor =
+            Arc::new(Redactor::new(&self.settings.logging.redaction));
+        let clock = self.clock;
+        let email_sender = self.email_sender;
+        let example_repository = self.example_repository.unwrap_or_else(|| {
+            Arc::new(PgExampleRepository::new(db_pools.clone(), clock.clone()))
+        });
+        let user_repository = self
+            .user_repository
+            .unwrap_or_else(|| Arc::new(PgUserRepository(db_pool.clone())));
+        let connection_pool = Data::new(db_pool.clone());
+        let db_pools = Data::new(db_pools);
+        let log_filter = Data::new(self.log_filter);
+        let max_payload_bytes = self.settings.app.max_payload_bytes;
+        let hmac_secret = HmacSecret(self.settings.app.hmac_secret);
+        telemetry::set_sensitive_hash_key(
+            hmac_secret.0.expose_secret().as_bytes(),
+        );
+        let migrate_on_startup = self.settings.database.migrate_on_startup;
+        let transaction_settings = self.settings.database.transactions;
+        let api_settings = self.settings.api;
+        let message_store = CookieMessageStore::builder(Key::from(
+            hmac_secret.0.expose_secret().as_bytes(),
+        ))
+        .build();
+        let message_framework =
+            FlashMessagesFramework::builder(message_store).build();
+        // Build the app
+        let server = HttpServer::new(move || {
+            App::new()
+                // Innermost, so only the final attempt's response is saved
+                .wrap(Transactional::new(
+                    connection_pool.get_ref().clone(),
+                    transaction_settings.clone(),
+                ))
+                // Replayed responses are still audited
+                .wrap(Idempotency::new(
+                    connection_pool.get_ref().clone(),
+                    user_repository.clone(),
+                    clock.clone(),
+                ))
+                .wrap(AuditLog::new(
+                    connection_pool.get_ref().clone(),
+                    redactor.clone(),
+                    clock.clone(),
+                ))
+                .wrap(message_framework.clone())
+                .wrap(ReadYourWrites::new(
+                    clock.clone(),
+                    read_your_writes_window,
+                ))
+                .wrap(RequestMetrics)
+                .wrap(TracingLogger::default())
+                .configure(|config| {
+                    registry::configure_app(config, &api_settings)
+                })
+                .app_data(connection_pool.clone())
+                .app_data(db_pools.clone())
+                .app_data(readiness_checks.clone())
+                .app_data(log_filter.clone())
+                .app_data(Data::<dyn Clock>::from(clock.clone()))
+                .app_data(Data::<dyn EmailSender>::from(email_sender.clone()))
+                .app_data(Data::<dyn ExampleRepository>::from(
+                    example_repository.clone(),
+                ))
+                .app_data(Data::<dyn UserRepository>::from(
+                    user_repository.clone(),
+                ))
+                .app_data(Data::new(hmac_secret.clone()))
+                // Also read by middleware extracting the body
+                .app_data(PayloadConfig::new(max_payload_bytes))
+                .app_data(JsonConfig::default().limit(max_payload_bytes))
+                .app_data(FormConfig::default().limit(max_payload_bytes))
+        })
+        // Signals are handled by `Shutdown::run_until_stopped` instead
+        .disable_signals()
+        .shutdown_timeout(shutdown.timeout().as_secs())
+        .listen(listener)?
+        .run();
+
+        Ok(Application {
+            port,
+            server,
+            shutdown,
+            workers,
+            scheduler,
+            replica_health_checks,
+            db_pool,
+            migrate_on_startup,
+        })
+    }
+}
+
+/// Connections are only made once the pool is first used.
+pub fn get_connection_pool(settings: &DatabaseSettings) -> PgPool {
+    settings
+        .pool_options()
+        .connect_lazy_with(settings.with_db())
+}
+
+/// Pool for a read replica, connected once it's first used.
+pub fn get_replica_pool(
+    settings: &DatabaseSettings,
+    replica: &ReplicaSettings,
+) -> PgPool {
+    settings
+        .pool_options()
+        .connect_lazy_with(settings.replica_with_db(replica))
+}
+
+/// Connect to the database, or only once the pool is first used if the pool
+/// settings are `lazy`.
+pub async fn connect(
+    settings: &DatabaseSettings,
+) -> Result<PgPool, sqlx::Error> {
+    match settings.pool.lazy {
+        true => Ok(get_connection_pool(settings)),
+        false => {
+            settings
+                .pool_options()
+                .connect_with(settings.with_db())
+                .await
+        }
+    }
 }
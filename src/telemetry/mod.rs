@@ -0,0 +1,461 @@
+mod redaction;
+
+pub use redaction::*;
+
+use crate::configuration::{
+    LogDestination, LogFormat, LogRotation, LoggingSettings, TelemetrySettings,
+};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::subscriber::set_global_default;
+use tracing::Subscriber;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::{fmt, reload, Layer};
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use uuid::Uuid;
+
+/// Logs are formatted and redacted as set in `settings`, but written to `sink`
+/// rather than `settings.destination` so tests can capture or discard them.
+/// Use `make_writer` for the configured destination.
+///
+/// The `tracer` assigns OpenTelemetry trace ids to spans, which
+/// `TracingLogger` records as `trace_id` on each request's root span so they
+/// appear in the logs. Spans are redacted before they're exported too.
+///
+/// The returned `LogFilter` changes the subscriber's filter while it's in use.
+pub fn get_subscriber<Sink>(
+    name: String,
+    settings: &LoggingSettings,
+    sink: Sink,
+    tracer: Tracer,
+) -> (impl Subscriber + Send + Sync, LogFilter)
+where
+    //TODO: learn about HRTBs
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let log_filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(filter_directives(settings)));
+    let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    // Colours are only readable in a terminal
+    let ansi = matches!(
+        settings.destination,
+        LogDestination::Stdout | LogDestination::Stderr
+    );
+    let formatting_layer = match settings.format {
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_ansi(ansi)
+            .with_writer(sink)
+            .boxed(),
+        LogFormat::Compact => fmt::layer()
+            .compact()
+            .with_ansi(ansi)
+            .with_writer(sink)
+            .boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(sink).boxed(),
+        LogFormat::Bunyan => JsonStorageLayer
+            .and_then(BunyanFormattingLayer::new(name, sink))
+            .boxed(),
+    };
+    let (log_filter, handle) = reload::Layer::new(log_filter);
+    let subscriber =
+        Registry::default()
+            .with(log_filter)
+            .with(RedactingLayer::new(
+                otel_layer.and_then(formatting_layer),
+                Redactor::new(&settings.redaction),
+            ));
+    (subscriber, LogFilter::new(handle))
+}
+
+/// Handle to read and replace the subscriber's `EnvFilter` at runtime, e.g. to
+/// enable debug logs for a module while investigating an issue.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    pending_revert: Arc<Mutex<Option<PendingRevert>>>,
+}
+
+struct PendingRevert {
+    id: Uuid,
+    /// Directive to restore
+    directive: String,
+    at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LogFilterStatus {
+    pub directive: String,
+    /// When the directive will be reverted, if it's temporary
+    pub revert_at: Option<DateTime<Utc>>,
+}
+
+impl LogFilter {
+    fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self {
+            handle,
+            pending_revert: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn status(&self) -> Result<LogFilterStatus, anyhow::Error> {
+        let directive = self
+            .handle
+            .with_current(ToString::to_string)
+            .context("Failed to read log filter")?;
+        let revert_at = self
+            .pending_revert
+            .lock()
+            .map_err(|_| anyhow!("Log filter lock poisoned"))?
+            .as_ref()
+            .map(|pending| pending.at);
+        Ok(LogFilterStatus {
+            directive,
+            revert_at,
+        })
+    }
+
+    /// Replace the active filter. If `revert_after` is set, the filter that
+    /// was active before any temporary changes is restored once it elapses.
+    ///
+    /// Each change cancels any pending revert, so must be called from within
+    /// an actix runtime to schedule a new one.
+    pub fn set(
+        &self,
+        filter: EnvFilter,
+        revert_after: Option<Duration>,
+    ) -> Result<LogFilterStatus, anyhow::Error> {
+        // Checked first, so a failure leaves the filter and any pending
+        // revert as they were
+        let revert = match revert_after {
+            Some(revert_after) => Some((
+                revert_after,
+                chrono::Duration::from_std(revert_after)
+                    .ok()
+                    .and_then(|after| Utc::now().checked_add_signed(after))
+                    .context("Revert timeout is too long")?,
+            )),
+            None => None,
+        };
+        let mut pending_revert = self
+            .pending_revert
+            .lock()
+            .map_err(|_| anyhow!("Log filter lock poisoned"))?;
+        let previous = match pending_revert.as_ref() {
+            Some(pending) => pending.directive.clone(),
+            None => self
+                .handle
+                .with_current(ToString::to_string)
+                .context("Failed to read log filter")?,
+        };
+        let directive = filter.to_string();
+        self.handle
+            .reload(filter)
+            .context("Failed to reload log filter")?;
+        tracing::info!(%directive, ?revert_after, "Log filter changed");
+
+        *pending_revert = match revert {
+            Some((revert_after, at)) => {
+                let id = Uuid::new_v4();
+                let log_filter = self.clone();
+                actix_web::rt::spawn(async move {
+                    actix_web::rt::time::sleep(revert_after).await;
+                    if let Err(e) = log_filter.revert(id) {
+                        tracing::error!(
+                            error = ?e,
+                            "Failed to revert log filter"
+                        );
+                    }
+                });
+                Some(PendingRevert {
+                    id,
+                    directive: previous,
+                    at,
+                })
+            }
+            None => None,
+        };
+        drop(pending_revert);
+        self.status()
+    }
+
+    /// Restore the filter from before a temporary change, unless it's since
+    /// been replaced.
+    fn revert(&self, id: Uuid) -> Result<(), anyhow::Error> {
+        let mut pending_revert = self
+            .pending_revert
+            .lock()
+            .map_err(|_| anyhow!("Log filter lock poisoned"))?;
+        if pending_revert.as_ref().map(|pending| pending.id) != Some(id) {
+            return Ok(());
+        }
+        if let Some(pending) = pending_revert.take() {
+            let filter = EnvFilter::try_new(&pending.directive)
+                .context("Failed to parse previous log filter")?;
+            self.handle
+                .reload(filter)
+                .context("Failed to reload log filter")?;
+            tracing::info!(directive = %pending.directive, "Log filter reverted");
+        }
+        Ok(())
+    }
+}
+
+/// `EnvFilter` directives for the default level followed by each module's
+/// override, e.g. `info,sqlx=warn`.
+fn filter_directives(settings: &LoggingSettings) -> String {
+    std::iter::once(settings.level.clone())
+        .chain(
+            settings
+                .modules
+                .iter()
+                .map(|(module, level)| format!("{module}={level}")),
+        )
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Writer for the configured log destination, to pass to `get_subscriber`.
+pub fn make_writer(
+    destination: &LogDestination,
+) -> Result<BoxMakeWriter, anyhow::Error> {
+    let writer = match destination {
+        LogDestination::Stdout => BoxMakeWriter::new(std::io::stdout),
+        LogDestination::Stderr => BoxMakeWriter::new(std::io::stderr),
+        LogDestination::File {
+            directory,
+            prefix,
+            rotation,
+        } => {
+            let rotation = match rotation {
+                LogRotation::Minutely => Rotation::MINUTELY,
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            let appender = RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix(prefix)
+                .build(directory)
+                .with_context(|| {
+                    format!("Failed to open log file in {directory:?}")
+                })?;
+            BoxMakeWriter::new(appender)
+        }
+    };
+    Ok(writer)
+}
+
+/// Build the OpenTelemetry tracer provider, exporting spans over OTLP/HTTP if
+/// an endpoint is configured, and propagate W3C `traceparent` headers from
+/// incoming requests.
+///
+/// Call `shutdown` on the provider before exiting to flush pending spans.
+pub fn init_tracer_provider(
+    name: String,
+    settings: &TelemetrySettings,
+) -> Result<SdkTracerProvider, anyhow::Error> {
+    opentelemetry::global::set_text_map_propagator(
+        TraceContextPropagator::new(),
+    );
+
+    let resource = Resource::builder().with_service_name(name).build();
+    let mut provider = SdkTracerProvider::builder().with_resource(resource);
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to build OTLP span exporter")?;
+        provider = provider.with_batch_exporter(exporter);
+    }
+    Ok(provider.build())
+}
+
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    LogTracer::init().expect("Failed to set logger");
+    tracing::info!("Logger set.");
+    set_global_default(subscriber).expect("Failed to set subscriber");
+    tracing::info!("Subscriber set.");
+}
+
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    actix_web::rt::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+/// Creates the `info_span` and then enters the span to create the guard.
+///
+/// Removed request_id generation as `tracing_actix_web::TracingLogger` takes
+/// care of that.
+///
+/// First arg is a string literal which is used as a "name" for the span.
+/// The est of the arguments are fields to be displayed within the trace. `%`
+/// can be used to automatically name the field. For example, `%field`, if field
+/// had a value of 69, would be displayed as `field: 69`.
+/// ```rust
+/// # use actix_web::{web, HttpResponse};
+/// # use actix_web_template::init_request_trace;
+/// pub async fn example_get(email: web::Path<String>) -> HttpResponse {
+///     init_request_trace!("Processing new GET request", %email);
+///     HttpResponse::Ok().body(email.into_inner())
+/// }
+/// ```
+#[macro_export]
+macro_rules! init_request_trace {
+    ($name:literal, $($field:tt)*) => {
+        // let request_id = ::uuid::Uuid::new_v4();
+        let request_span = ::tracing::info_span!(
+            $name,
+            // %request_id,
+            $($field)*
+        );
+        let _request_span_guard = request_span.enter();
+    };
+    ($name:literal) => {
+        // let request_id = ::uuid::Uuid::new_v4();
+        let request_span = ::tracing::info_span!(
+            $name,
+            // %request_id,
+        );
+        let _request_span_guard = request_span.enter();
+    };
+}
+
+/// Same as `init_request_trace!`, but the fields in square brackets are
+/// sensitive so their values are hashed rather than logged. The hashes are
+/// stable and keyed, see `set_sensitive_hash_key`, so requests with the same
+/// value can still be correlated.
+/// ```rust
+/// # use actix_web::{web, HttpResponse};
+/// # use actix_web_template::init_sensitive_request_trace;
+/// pub async fn example_get(email: web::Path<String>) -> HttpResponse {
+///     init_sensitive_request_trace!("Processing GET", [email = email]);
+///     HttpResponse::Ok().body(email.into_inner())
+/// }
+/// ```
+/// Other fields follow the brackets, as in `init_request_trace!`:
+/// ```rust
+/// # use actix_web_template::init_sensitive_request_trace;
+/// # let (email, name) = ("barry@barry.com", "Barry");
+/// init_sensitive_request_trace!("Processing POST", [email = email], %name);
+/// ```
+#[macro_export]
+macro_rules! init_sensitive_request_trace {
+    (
+        $name:literal,
+        [$($sensitive:ident = $value:expr),+ $(,)?]
+        $(, $($field:tt)*)?
+    ) => {
+        $crate::init_request_trace!(
+            $name,
+            $($sensitive = %$crate::telemetry::hash_sensitive(&$value),)+
+            $($($field)*)?
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider;
+
+    /// The filter can only be changed while the subscriber is alive
+    fn subscriber() -> (impl Subscriber, LogFilter) {
+        let tracer = SdkTracerProvider::builder().build().tracer("test");
+        get_subscriber(
+            "test".into(),
+            &LoggingSettings::default(),
+            std::io::sink,
+            tracer,
+        )
+    }
+
+    #[test]
+    fn module_levels_override_default_level() {
+        let settings = LoggingSettings {
+            level: "info".into(),
+            modules: [
+                ("sqlx".to_string(), "warn".to_string()),
+                (
+                    "actix_web_template::routes".to_string(),
+                    "debug".to_string(),
+                ),
+            ]
+            .into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "info,actix_web_template::routes=debug,sqlx=warn",
+            filter_directives(&settings)
+        );
+    }
+
+    #[actix_web::test]
+    async fn temporary_log_filter_is_reverted() {
+        let (_subscriber, log_filter) = subscriber();
+        let original = log_filter.status().unwrap().directive;
+
+        let status = log_filter
+            .set(EnvFilter::new("debug"), Some(Duration::from_millis(50)))
+            .unwrap();
+        assert_eq!("debug", status.directive);
+        assert!(status.revert_at.is_some());
+
+        // Reverts to the filter from before any temporary changes
+        log_filter
+            .set(EnvFilter::new("trace"), Some(Duration::from_millis(50)))
+            .unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+
+        let status = log_filter.status().unwrap();
+        assert_eq!(original, status.directive);
+        assert!(status.revert_at.is_none());
+    }
+
+    #[actix_web::test]
+    async fn permanent_log_filter_cancels_pending_revert() {
+        let (_subscriber, log_filter) = subscriber();
+        log_filter
+            .set(EnvFilter::new("debug"), Some(Duration::from_millis(50)))
+            .unwrap();
+        log_filter.set(EnvFilter::new("warn"), None).unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+
+        let status = log_filter.status().unwrap();
+        assert_eq!("warn", status.directive);
+        assert!(status.revert_at.is_none());
+    }
+
+    #[actix_web::test]
+    async fn invalid_revert_timeout_leaves_filter_unchanged() {
+        let (_subscriber, log_filter) = subscriber();
+        let pending = log_filter
+            .set(EnvFilter::new("debug"), Some(Duration::from_secs(60)))
+            .unwrap();
+
+        let result =
+            log_filter.set(EnvFilter::new("trace"), Some(Duration::MAX));
+
+        assert!(result.is_err());
+        let status = log_filter.status().unwrap();
+        assert_eq!("debug", status.directive);
+        assert_eq!(pending.revert_at, status.revert_at);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::configuration::RedactionSettings;
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use sha2::Sha256;
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::sync::RwLock;
+use tracing::field::{self, display, DisplayValue, Field, FieldSet, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const REDACTED: &str = "[REDACTED]";
+/// Escape codes the `pretty` and `compact` formats may wrap field names in
+const ANSI_ESCAPES: &str = r"(?:\x1b\[[0-9;]*m)*";
+
+/// Key for `hash_sensitive`, random until `set_sensitive_hash_key` is called
+static SENSITIVE_HASH_KEY: Lazy<RwLock<Hmac<Sha256>>> =
+    Lazy::new(|| RwLock::new(keyed_hash(&rand::random::<[u8; 32]>())));
+
+/// Key the hashes of sensitive values, e.g. with the app's HMAC secret, so
+/// they can't be reversed by hashing guesses.
+pub fn set_sensitive_hash_key(key: &[u8]) {
+    *SENSITIVE_HASH_KEY
+        .write()
+        .expect("Sensitive hash key lock poisoned") = keyed_hash(key);
+}
+
+/// Short, stable, keyed hash of a sensitive value, so requests can still be
+/// correlated in the logs without printing the value.
+pub fn hash_sensitive(value: impl Display) -> String {
+    let mac = SENSITIVE_HASH_KEY
+        .read()
+        .expect("Sensitive hash key lock poisoned")
+        .clone();
+    hash_with(mac, value)
+}
+
+fn keyed_hash(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size")
+}
+
+fn hash_with(mut mac: Hmac<Sha256>, value: impl Display) -> String {
+    mac.update(value.to_string().as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..8])
+}
+
+/// Masks sensitive values in formatted log records.
+///
+/// JSON records (the `json` and `bunyan` formats) have the values of matching
+/// keys masked, while other records have `field=value` and `field: value`
+/// pairs masked. Text matching a pattern is masked in either.
+pub struct Redactor {
+    fields: Vec<String>,
+    text_fields: Option<Regex>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Self {
+        let fields: Vec<String> = settings
+            .fields
+            .iter()
+            .map(|field| field.to_lowercase())
+            .collect();
+        let text_fields = match fields.is_empty() {
+            true => None,
+            false => {
+                let names = fields.iter().map(|f| regex::escape(f)).join("|");
+                let pattern = format!(
+                    r#"(?i)\b({names})({ANSI_ESCAPES}(?:=|: ){ANSI_ESCAPES})("[^"]*"|[^\s,\x1b]+)"#
+                );
+                Some(Regex::new(&pattern).expect("Field names are escaped"))
+            }
+        };
+        let patterns = settings
+            .patterns
+            .iter()
+            .map(|pattern| pattern.0.clone())
+            .collect();
+
+        Self {
+            fields,
+            text_fields,
+            patterns,
+        }
+    }
+
+    pub fn redact<'a>(&self, record: &'a str) -> Cow<'a, str> {
+        match serde_json::from_str::<Value>(record) {
+            Ok(mut value @ Value::Object(_)) => {
+                match self.redact_value(&mut value) {
+                    true => {
+                        let newline = record.ends_with('\n');
+                        let mut redacted = value.to_string();
+                        if newline {
+                            redacted.push('\n');
+                        }
+                        Cow::Owned(redacted)
+                    }
+                    // Re-serialising would reorder the keys
+                    false => Cow::Borrowed(record),
+                }
+            }
+            _ => self.redact_text(record),
+        }
+    }
+
+    /// Mask sensitive keys and patterns in `value` in place, returning
+    /// whether anything was masked.
+    pub(crate) fn redact_value(&self, value: &mut Value) -> bool {
+        match value {
+            Value::Object(map) => {
+                let mut redacted = false;
+                for (key, value) in map.iter_mut() {
+                    redacted |= match self.is_sensitive(key) {
+                        true => {
+                            *value = Value::String(REDACTED.into());
+                            true
+                        }
+                        false => self.redact_value(value),
+                    };
+                }
+                redacted
+            }
+            Value::Array(values) => {
+                let mut redacted = false;
+                for value in values {
+                    redacted |= self.redact_value(value);
+                }
+                redacted
+            }
+            Value::String(text) => match self.redact_patterns(text) {
+                Cow::Owned(redacted) => {
+                    *text = redacted;
+                    true
+                }
+                Cow::Borrowed(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Span fields are named after their expression, e.g. `form.password`, so
+    /// only the last segment is compared.
+    pub(crate) fn is_sensitive(&self, key: &str) -> bool {
+        let name = key.rsplit('.').next().unwrap_or(key).to_lowercase();
+        self.fields.contains(&name)
+    }
+
+    pub(crate) fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let redacted = self.redact_patterns(text);
+        match &self.text_fields {
+            Some(text_fields) => match text_fields
+                .replace_all(&redacted, format!("${{1}}${{2}}{REDACTED}"))
+            {
+                Cow::Owned(masked) => Cow::Owned(masked),
+                Cow::Borrowed(_) => redacted,
+            },
+            None => redacted,
+        }
+    }
+
+    fn redact_patterns<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.patterns
+            .iter()
+            .fold(Cow::Borrowed(text), |text, pattern| {
+                match pattern.replace_all(&text, REDACTED) {
+                    Cow::Owned(redacted) => Cow::Owned(redacted),
+                    Cow::Borrowed(_) => text,
+                }
+            })
+    }
+}
+
+/// Wraps a layer, redacting span and event fields before it sees them, so
+/// sensitive values reach neither the logs nor exported traces.
+///
+/// Sensitive fields are masked whatever their type, while text values have
+/// patterns and `field=value` pairs masked as in `Redactor::redact`.
+pub struct RedactingLayer<L> {
+    inner: L,
+    redactor: Redactor,
+}
+
+impl<L> RedactingLayer<L> {
+    pub fn new(inner: L, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl<S, L> Layer<S> for RedactingLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber)
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(
+        &self,
+        metadata: &'static Metadata<'static>,
+    ) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(
+        &self,
+        attrs: &Attributes<'_>,
+        id: &Id,
+        ctx: Context<'_, S>,
+    ) {
+        let metadata = attrs.metadata();
+        let values =
+            RedactedValues::new(&self.redactor, metadata.fields(), |v| {
+                attrs.record(v)
+            });
+        let refs = values.as_refs();
+        let value_set = metadata.fields().value_set_all(&refs);
+        let attrs = match attrs.parent() {
+            Some(parent) => {
+                Attributes::child_of(parent.clone(), metadata, &value_set)
+            }
+            None if attrs.is_root() => {
+                Attributes::new_root(metadata, &value_set)
+            }
+            None => Attributes::new(metadata, &value_set),
+        };
+        self.inner.on_new_span(&attrs, id, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let fields = match ctx.metadata(span) {
+            Some(metadata) => metadata.fields(),
+            None => return,
+        };
+        let redacted =
+            RedactedValues::new(&self.redactor, fields, |v| values.record(v));
+        let refs = redacted.as_refs();
+        let value_set = fields.value_set_all(&refs);
+        self.inner.on_record(span, &Record::new(&value_set), ctx)
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let values =
+            RedactedValues::new(&self.redactor, metadata.fields(), |v| {
+                event.record(v)
+            });
+        let refs = values.as_refs();
+        let value_set = metadata.fields().value_set_all(&refs);
+        let event = match event.is_contextual() {
+            true => Event::new(metadata, &value_set),
+            false => Event::new_child_of(
+                event.parent().cloned(),
+                metadata,
+                &value_set,
+            ),
+        };
+        self.inner.on_event(&event, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    /// Lets the inner layer be found, e.g. `tracing_opentelemetry`'s context
+    /// used to propagate `traceparent`.
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        match id == TypeId::of::<Self>() {
+            true => Some(self as *const Self as *const ()),
+            false => self.inner.downcast_raw(id),
+        }
+    }
+}
+
+/// A field's value after redaction, in a form which can be recorded again.
+enum RedactedValue {
+    Str(String),
+    /// Recorded with `Debug`, which is kept unquoted
+    Debug(DisplayValue<String>),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    F64(f64),
+    Bool(bool),
+}
+
+impl RedactedValue {
+    fn as_value(&self) -> &dyn field::Value {
+        match self {
+            RedactedValue::Str(value) => value,
+            RedactedValue::Debug(value) => value,
+            RedactedValue::I64(value) => value,
+            RedactedValue::U64(value) => value,
+            RedactedValue::I128(value) => value,
+            RedactedValue::U128(value) => value,
+            RedactedValue::F64(value) => value,
+            RedactedValue::Bool(value) => value,
+        }
+    }
+}
+
+/// Every recorded field's redacted value, indexed by the field's position in
+/// its `FieldSet`.
+struct RedactedValues<'a> {
+    redactor: &'a Redactor,
+    values: Vec<Option<RedactedValue>>,
+}
+
+impl<'a> RedactedValues<'a> {
+    fn new(
+        redactor: &'a Redactor,
+        fields: &FieldSet,
+        record: impl FnOnce(&mut Self),
+    ) -> Self {
+        let mut values = Self {
+            redactor,
+            values: (0..fields.len()).map(|_| None).collect(),
+        };
+        record(&mut values);
+        values
+    }
+
+    fn as_refs(&self) -> Vec<Option<&dyn field::Value>> {
+        self.values
+            .iter()
+            .map(|value| value.as_ref().map(RedactedValue::as_value))
+            .collect()
+    }
+
+    fn insert(&mut self, field: &Field, value: RedactedValue) {
+        let value = match self.redactor.is_sensitive(field.name()) {
+            true => RedactedValue::Str(REDACTED.into()),
+            false => value,
+        };
+        if let Some(slot) = self.values.get_mut(field.index()) {
+            *slot = Some(value);
+        }
+    }
+}
+
+impl Visit for RedactedValues<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, RedactedValue::F64(value))
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, RedactedValue::I64(value))
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, RedactedValue::U64(value))
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.insert(field, RedactedValue::I128(value))
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.insert(field, RedactedValue::U128(value))
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, RedactedValue::Bool(value))
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = self.redactor.redact_text(value).into_owned();
+        self.insert(field, RedactedValue::Str(value))
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let value = format!("{value:?}");
+        let value = self.redactor.redact_text(&value).into_owned();
+        self.insert(field, RedactedValue::Debug(display(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactionSettings::default())
+    }
+
+    #[test]
+    fn sensitive_json_fields_are_masked() {
+        let record = r#"{"msg":"Login","form.password":"hunter2","n":[1]}"#;
+
+        let redacted = redactor().redact(record);
+
+        assert!(!redacted.contains("hunter2"));
+        assert!(redacted.contains(r#""form.password":"[REDACTED]""#));
+    }
+
+    #[test]
+    fn patterns_are_masked_in_json_and_text() {
+        let json = r#"{"msg":"Sent to barry@barry.com"}"#;
+        let text =
+            "INFO Authorization: Bearer abc.def-123 from barry@barry.com";
+
+        let redacted_json = redactor().redact(json);
+        let redacted_text = redactor().redact(text);
+
+        assert_eq!(r#"{"msg":"Sent to [REDACTED]"}"#, redacted_json);
+        assert_eq!(
+            "INFO Authorization: [REDACTED] from [REDACTED]",
+            redacted_text
+        );
+    }
+
+    #[test]
+    fn sensitive_text_fields_are_masked() {
+        let record = "INFO login: password=hunter2 token: \"a b\" name=barry\n";
+
+        let redacted = redactor().redact(record);
+
+        assert_eq!(
+            "INFO login: password=[REDACTED] token: [REDACTED] name=barry\n",
+            redacted
+        );
+    }
+
+    #[test]
+    fn records_without_sensitive_values_are_unchanged() {
+        let record = r#"{"z":"last","a":"first"}"#;
+
+        assert!(matches!(redactor().redact(record), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn sensitive_values_hash_consistently() {
+        let hash = hash_sensitive("barry@barry.com");
+
+        assert_eq!(hash, hash_sensitive("barry@barry.com"));
+        assert_ne!(hash, hash_sensitive("bazza@barry.com"));
+        assert_eq!(16, hash.len());
+    }
+
+    #[test]
+    fn sensitive_values_hash_differently_for_each_key() {
+        let hash = |key: &[u8]| hash_with(keyed_hash(key), "barry@barry.com");
+
+        assert_eq!(hash(b"key"), hash(b"key"));
+        assert_ne!(hash(b"key"), hash(b"other key"));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::utils::{spawn_app, spawn_app_with_settings};
+use actix_web_template::api_version::{ApiVersion, VersionDeprecation};
+use actix_web_template::endpoint::{example_get, example_post};
+use chrono::{TimeZone, Utc};
+
+const NAME: &str = "Barry Barryfield";
+const EMAIL: &str = "barry@barry.com";
+
+#[tokio::test]
+async fn deprecated_version_responses_have_deprecation_headers() {
+    let test_app = spawn_app_with_settings(
+        |settings| {
+            settings.api.deprecated_versions = vec![VersionDeprecation {
+                version: ApiVersion::V1,
+                deprecated_at: Utc
+                    .with_ymd_and_hms(2027, 1, 1, 0, 0, 0)
+                    .unwrap(),
+                sunset_at: Some(
+                    Utc.with_ymd_and_hms(2027, 7, 1, 0, 0, 0).unwrap(),
+                ),
+            }]
+        },
+        |builder| builder,
+    )
+    .await;
+    let address = test_app.address;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{address}{}", example_get::url_v1(EMAIL)))
+        .send()
+        .await
+        .expect("GET request failed");
+
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(
+        "@1798761600",
+        response.headers().get("Deprecation").unwrap()
+    );
+    assert!(response.headers().contains_key("Sunset"));
+}
+
+#[tokio::test]
+async fn versions_are_not_deprecated_by_default() {
+    let test_app = spawn_app().await;
+    let address = test_app.address;
+    let client = reqwest::Client::new();
+
+    for url in [example_get::url_v1(EMAIL), example_get::url(EMAIL)] {
+        let response = client
+            .get(format!("{address}{url}"))
+            .send()
+            .await
+            .expect("GET request failed");
+
+        assert_eq!(404, response.status().as_u16());
+        assert!(!response.headers().contains_key("Deprecation"), "{url}");
+        assert!(!response.headers().contains_key("Sunset"), "{url}");
+    }
+}
+
+#[tokio::test]
+async fn unversioned_paths_are_deprecated_aliases_of_v1() {
+    let test_app = spawn_app().await;
+    let address = test_app.address;
+    let client = reqwest::Client::new();
+    let unversioned_post = example_post::V1_PATH.trim_start_matches("/v1");
+    let unversioned_get = example_get::url_v1(EMAIL).replacen("/v1", "", 1);
+
+    let post_response = client
+        .post(format!("{address}{unversioned_post}"))
+        .form(&[("name", NAME), ("email", EMAIL)])
+        .send()
+        .await
+        .expect("POST request failed");
+    let get_response = client
+        .get(format!("{address}{unversioned_get}"))
+        .send()
+        .await
+        .expect("GET request failed");
+
+    assert_eq!(200, post_response.status().as_u16());
+    assert!(post_response.headers().contains_key("Deprecation"));
+    assert_eq!(200, get_response.status().as_u16());
+    assert!(get_response.headers().contains_key("Deprecation"));
+    let stored = client
+        .get(format!("{address}{}", example_get::url_v1(EMAIL)))
+        .send()
+        .await
+        .expect("GET request failed");
+    assert_eq!(200, stored.status().as_u16());
+    assert!(!stored.headers().contains_key("Deprecation"));
+}
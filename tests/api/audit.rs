@@ -0,0 +1,173 @@
+use crate::utils::{
+    spawn_app, spawn_app_with, spawn_app_with_settings, FixedClock, TestUser,
+};
+use actix_web_template::audit::PAYLOAD_UNAVAILABLE;
+use actix_web_template::endpoint::{
+    audit_events, example_get, example_post, login, set_log_filter,
+};
+use chrono::{TimeZone, Utc};
+use reqwest::StatusCode;
+
+#[tokio::test]
+async fn audited_requests_are_recorded_with_redacted_payload() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let address = &test_app.address;
+    let body = [("name", "Barry"), ("email", "barry@barry.com")];
+
+    client
+        .post(format!("{address}{}", example_post::PATH))
+        .form(&body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    client
+        .get(format!("{address}{}", example_get::url("barry@barry.com")))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let events = test_app.get_audit_events("").await;
+    assert_eq!(1, events.len());
+    let event = &events[0];
+    assert_eq!(example_post::PATH, event.route);
+    assert_eq!("POST", event.method);
+    assert_eq!(200, event.status);
+    assert_eq!(None, event.actor);
+    let payload = event.payload.as_deref().unwrap();
+    assert!(payload.contains("Barry"), "{payload}");
+    assert!(!payload.contains("barry@barry.com"), "{payload}");
+}
+
+#[tokio::test]
+async fn requests_with_unreadable_bodies_are_recorded() {
+    let test_app = spawn_app_with_settings(
+        |settings| settings.app.max_payload_bytes = 16,
+        |builder| builder,
+    )
+    .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .form(&[("name", "Barry"), ("email", "barry@barry.com")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    let events = test_app.get_audit_events("").await;
+    assert_eq!(1, events.len());
+    assert_eq!(413, events[0].status);
+    assert_eq!(Some(PAYLOAD_UNAVAILABLE), events[0].payload.as_deref());
+}
+
+#[tokio::test]
+async fn audited_requests_record_authenticated_actor() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let address = &test_app.address;
+    let user = &test_app.test_user;
+
+    // Unauthenticated attempts are recorded too
+    client
+        .put(format!("{address}{}", set_log_filter::PATH))
+        .json(&serde_json::json!({ "directive": "info" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    client
+        .put(format!("{address}{}", set_log_filter::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({ "directive": "info" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let events = test_app
+        .get_audit_events(&format!("?actor={}", user.username))
+        .await;
+    assert_eq!(1, events.len());
+    assert_eq!(200, events[0].status);
+    assert_eq!(Some(&user.username), events[0].actor.as_ref());
+
+    let events = test_app
+        .get_audit_events(&format!("?route={}", set_log_filter::PATH))
+        .await;
+    assert_eq!(2, events.len());
+    assert_eq!(401, events[1].status);
+    assert_eq!(None, events[1].actor);
+}
+
+#[tokio::test]
+async fn audit_events_require_credentials() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}{}", test_app.address, audit_events::PATH))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn audit_events_require_an_admin() {
+    let test_app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&test_app.db_pool).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}{}", test_app.address, audit_events::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+}
+
+#[tokio::test]
+async fn logins_record_the_user_as_actor() {
+    let test_app = spawn_app().await;
+    let user = &test_app.test_user;
+
+    test_app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": "wrong-password",
+        }))
+        .await;
+    test_app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": user.password,
+        }))
+        .await;
+
+    let events = test_app
+        .get_audit_events(&format!("?route={}", login::PATH))
+        .await;
+    assert_eq!(2, events.len());
+    assert_eq!(303, events[0].status);
+    assert_eq!(Some(&user.username), events[0].actor.as_ref());
+    assert_eq!(None, events[1].actor);
+}
+
+#[tokio::test]
+async fn audit_events_are_timestamped_by_the_app_clock() {
+    let now = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+    let test_app =
+        spawn_app_with(|builder| builder.clock(FixedClock(now))).await;
+
+    reqwest::Client::new()
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .form(&[("name", "Barry"), ("email", "barry@barry.com")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let events = test_app.get_audit_events("").await;
+    assert_eq!(1, events.len());
+    assert_eq!(now, events[0].occurred_at);
+}
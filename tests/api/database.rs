@@ -0,0 +1,75 @@
+use crate::utils::spawn_app;
+use actix_web_template::database::{ConstraintKind, DbError};
+use sqlx::{Executor, PgPool};
+
+async fn create_constrained_tables(pool: &PgPool) {
+    pool.execute(
+        r#"
+        CREATE TABLE parent (id INTEGER PRIMARY KEY);
+        CREATE TABLE child (
+            id INTEGER NOT NULL,
+            parent_id INTEGER REFERENCES parent (id),
+            age INTEGER CONSTRAINT child_age_positive CHECK (age > 0),
+            CONSTRAINT child_id_unique UNIQUE (id)
+        );
+        INSERT INTO parent (id) VALUES (1);
+        INSERT INTO child (id, parent_id, age) VALUES (1, 1, 1);
+        "#,
+    )
+    .await
+    .expect("Failed to create tables");
+}
+
+#[tokio::test]
+async fn constraint_violations_are_recognised() {
+    let test_app = spawn_app().await;
+    create_constrained_tables(&test_app.db_pool).await;
+
+    for (insert, kind, constraint) in [
+        (
+            "INSERT INTO child (id) VALUES (1)",
+            ConstraintKind::Unique,
+            "child_id_unique",
+        ),
+        (
+            "INSERT INTO child (id, parent_id) VALUES (2, 2)",
+            ConstraintKind::ForeignKey,
+            "child_parent_id_fkey",
+        ),
+        (
+            "INSERT INTO child (id) VALUES (NULL)",
+            ConstraintKind::NotNull,
+            "child.id",
+        ),
+        (
+            "INSERT INTO child (id, age) VALUES (2, 0)",
+            ConstraintKind::Check,
+            "child_age_positive",
+        ),
+    ] {
+        let error = DbError::from(
+            sqlx::query(insert)
+                .execute(&test_app.db_pool)
+                .await
+                .expect_err("Insert should have failed"),
+        );
+
+        assert_eq!(Some(kind), error.kind(), "{insert}");
+        assert_eq!(Some(constraint), error.constraint(), "{insert}");
+    }
+}
+
+#[tokio::test]
+async fn other_errors_are_not_constraint_violations() {
+    let test_app = spawn_app().await;
+
+    let error = DbError::from(
+        sqlx::query("SELECT * FROM missing_table")
+            .execute(&test_app.db_pool)
+            .await
+            .expect_err("Query should have failed"),
+    );
+
+    assert!(matches!(error, DbError::Other(_)));
+    assert_eq!(None, error.constraint());
+}
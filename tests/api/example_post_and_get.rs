@@ -1,9 +1,7 @@
-use crate::utils::spawn_app;
+use crate::utils::{spawn_app, spawn_app_with, FixedClock};
 use actix_web_template::endpoint::{example_get, example_post};
 use actix_web_template::routes::ExampleGetResponse;
-use serde_urlencoded;
-use strfmt::strfmt;
-use urlencoding;
+use chrono::{TimeZone, Utc};
 
 //TODO: break this test down into 3 tests
 // (will only work if they can somehow be peformed sequentially with same db instance)
@@ -17,7 +15,7 @@ async fn example_post_returns_200_for_valid_form_data_and_get_returns_new_data()
     let client = reqwest::Client::new();
 
     let post_body =
-        serde_urlencoded::to_string(&[("name", NAME), ("email", EMAIL)])
+        serde_urlencoded::to_string([("name", NAME), ("email", EMAIL)])
             .expect("Failed to urlencode POST request");
 
     let post_response = client
@@ -39,13 +37,8 @@ async fn example_post_returns_200_for_valid_form_data_and_get_returns_new_data()
     assert_eq!(NAME, new_entry.name);
     assert_eq!(EMAIL, new_entry.email);
 
-    let email = EMAIL;
     let text_response = client
-        .get(format!(
-            "{address}{}",
-            strfmt!(example_get::PATH, email)
-                .expect("Failed to format request url")
-        ))
+        .get(format!("{address}{}", example_get::url(EMAIL)))
         .send()
         .await
         .expect("GET request failed")
@@ -55,7 +48,7 @@ async fn example_post_returns_200_for_valid_form_data_and_get_returns_new_data()
 
     println!("{:?}", text_response);
     let parsed_response: ExampleGetResponse =
-        serde_json::from_str(&*text_response)
+        serde_json::from_str(&text_response)
             .expect("Error parsing json from text");
     assert_eq!(NAME, parsed_response.name);
     assert_eq!(EMAIL, parsed_response.email);
@@ -69,12 +62,12 @@ async fn example_post_returns_400_for_invalid_form_data() {
 
     let invalid_bodies = vec![
         (
-            serde_urlencoded::to_string(&[("name", "barry barryfield")])
+            serde_urlencoded::to_string([("name", "barry barryfield")])
                 .unwrap(),
             "missing email",
         ),
         (
-            serde_urlencoded::to_string(&[("email", "barry@barry.com")])
+            serde_urlencoded::to_string([("email", "barry@barry.com")])
                 .unwrap(),
             "missing name",
         ),
@@ -112,12 +105,12 @@ async fn db_not_updated_after_failed_post_attempt() {
 
     let invalid_bodies = vec![
         (
-            serde_urlencoded::to_string(&[("name", "barry barryfield")])
+            serde_urlencoded::to_string([("name", "barry barryfield")])
                 .unwrap(),
             "missing email",
         ),
         (
-            serde_urlencoded::to_string(&[("email", "barry@barry.com")])
+            serde_urlencoded::to_string([("email", "barry@barry.com")])
                 .unwrap(),
             "missing name",
         ),
@@ -159,10 +152,7 @@ async fn example_get_returns_404_for_nonexistant_data() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(format!(
-            "{address}{}",
-            strfmt!(example_get::PATH, email => EMAIL).unwrap()
-        ))
+        .get(format!("{address}{}", example_get::url(EMAIL)))
         .send()
         .await
         .expect("GET request failed");
@@ -175,13 +165,12 @@ async fn example_get_returns_400_for_basic_injection() {
     const BAD_EMAIL: &str = "'' OR 1=1;";
     const NAME: &str = "barry barryfield";
     const EMAIL: &str = "barry_bazza@barry.com";
-    let bad_email: String = urlencoding::encode(BAD_EMAIL).into_owned();
     let test_app = spawn_app().await;
     let address = test_app.address;
     let client = reqwest::Client::new();
 
     let post_body =
-        serde_urlencoded::to_string(&[("name", NAME), ("email", EMAIL)])
+        serde_urlencoded::to_string([("name", NAME), ("email", EMAIL)])
             .expect("Failed to urlencode POST request");
 
     let post_response = client
@@ -204,13 +193,50 @@ async fn example_get_returns_400_for_basic_injection() {
     assert_eq!(EMAIL, new_entry.email);
 
     let bad_request_response = client
-        .get(format!(
-            "{address}{}",
-            strfmt!(example_get::PATH, email => bad_email).unwrap()
-        ))
+        .get(format!("{address}{}", example_get::url(BAD_EMAIL)))
         .send()
         .await
         .expect("GET request failed");
 
     assert_eq!(400, bad_request_response.status().as_u16());
 }
+
+#[tokio::test]
+async fn example_post_returns_409_for_existing_email() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let body = [("name", "Barry"), ("email", "barry@barry.com")];
+
+    let mut statuses = vec![];
+    for _ in 0..2 {
+        let response = client
+            .post(format!("{}{}", test_app.address, example_post::PATH))
+            .form(&body)
+            .send()
+            .await
+            .expect("POST request failed");
+        statuses.push(response.status().as_u16());
+    }
+
+    assert_eq!(vec![200, 409], statuses);
+}
+
+#[tokio::test]
+async fn examples_are_timestamped_by_the_app_clock() {
+    let now = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+    let test_app =
+        spawn_app_with(|builder| builder.clock(FixedClock(now))).await;
+
+    reqwest::Client::new()
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .form(&[("name", "Barry"), ("email", "barry@barry.com")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let saved = sqlx::query!("SELECT added_at FROM example")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved example");
+    assert_eq!(now, saved.added_at);
+}
@@ -8,7 +8,7 @@ async fn health_check_works() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{address}{}", health_check::PATH))
+        .get(format!("{address}{}", health_check::PATH))
         .send()
         .await
         .expect("Failed to execute request");
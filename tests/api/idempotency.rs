@@ -0,0 +1,195 @@
+use crate::utils::{spawn_app, TestUser};
+use actix_web_template::endpoint::example_post;
+use actix_web_template::idempotency::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED};
+use reqwest::StatusCode;
+
+const BODY: [(&str, &str); 2] =
+    [("name", "Barry"), ("email", "barry@barry.com")];
+
+#[tokio::test]
+async fn repeated_keys_replay_the_saved_response() {
+    let test_app = spawn_app().await;
+    let user = &test_app.test_user;
+    let url = format!("{}{}", test_app.address, example_post::PATH);
+    let client = reqwest::Client::new();
+
+    let mut responses = vec![];
+    for _ in 0..2 {
+        let response = client
+            .post(&url)
+            .header(IDEMPOTENCY_KEY, "retried")
+            .basic_auth(&user.username, Some(&user.password))
+            .form(&BODY)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        responses.push(response);
+    }
+
+    assert_eq!(StatusCode::OK, responses[0].status());
+    assert_eq!(StatusCode::OK, responses[1].status());
+    assert!(!responses[0].headers().contains_key(IDEMPOTENT_REPLAYED));
+    assert!(responses[1].headers().contains_key(IDEMPOTENT_REPLAYED));
+    // The retry didn't add a duplicate
+    let examples = sqlx::query!("SELECT id FROM example")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch examples");
+    assert_eq!(1, examples.len());
+}
+
+#[tokio::test]
+async fn keys_reused_for_a_different_request_are_rejected() {
+    let test_app = spawn_app().await;
+    let user = &test_app.test_user;
+    let url = format!("{}{}", test_app.address, example_post::PATH);
+    let client = reqwest::Client::new();
+
+    for (email, status) in [
+        ("barry@barry.com", StatusCode::OK),
+        ("bazza@barry.com", StatusCode::UNPROCESSABLE_ENTITY),
+    ] {
+        let response = client
+            .post(&url)
+            .header(IDEMPOTENCY_KEY, "reused")
+            .basic_auth(&user.username, Some(&user.password))
+            .form(&[("name", "Barry"), ("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_eq!(status, response.status());
+    }
+}
+
+#[tokio::test]
+async fn requests_repeating_an_in_flight_key_are_rejected() {
+    let test_app = spawn_app().await;
+    let user = &test_app.test_user;
+    // Claim the key without a response, as the in-flight request would
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency
+            (requester, idempotency_key, request_hash, created_at)
+        VALUES ($1, 'in-flight', 'in-flight', now())
+        "#,
+        user.username,
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .expect("Failed to claim key");
+
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .header(IDEMPOTENCY_KEY, "in-flight")
+        .basic_auth(&user.username, Some(&user.password))
+        .form(&BODY)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::CONFLICT, response.status());
+}
+
+#[tokio::test]
+async fn keys_are_scoped_to_the_authenticated_user() {
+    let test_app = spawn_app().await;
+    let url = format!("{}{}", test_app.address, example_post::PATH);
+    let client = reqwest::Client::new();
+    let other_user = TestUser::generate();
+    other_user.store(&test_app.db_pool).await;
+
+    let mut responses = vec![];
+    for user in [&test_app.test_user, &other_user] {
+        let response = client
+            .post(&url)
+            .header(IDEMPOTENCY_KEY, "shared")
+            .basic_auth(&user.username, Some(&user.password))
+            .form(&BODY)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        responses.push(response);
+    }
+
+    assert_eq!(StatusCode::OK, responses[0].status());
+    assert!(!responses[1].headers().contains_key(IDEMPOTENT_REPLAYED));
+    // Only the username is saved, nothing derived from the credentials
+    let mut requesters: Vec<String> =
+        sqlx::query_scalar("SELECT requester FROM idempotency")
+            .fetch_all(&test_app.db_pool)
+            .await
+            .expect("Failed to fetch saved responses");
+    requesters.sort();
+    let mut usernames =
+        vec![test_app.test_user.username.clone(), other_user.username];
+    usernames.sort();
+    assert_eq!(usernames, requesters);
+}
+
+#[tokio::test]
+async fn anonymous_keys_replay_identical_requests() {
+    let test_app = spawn_app().await;
+    let url = format!("{}{}", test_app.address, example_post::PATH);
+    let client = reqwest::Client::new();
+
+    let mut responses = vec![];
+    for email in ["barry@barry.com", "barry@barry.com", "bazza@barry.com"] {
+        let response = client
+            .post(&url)
+            .header(IDEMPOTENCY_KEY, "anonymous")
+            .form(&[("name", "Barry"), ("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request");
+        responses.push(response);
+    }
+
+    assert_eq!(StatusCode::OK, responses[0].status());
+    assert_eq!(StatusCode::OK, responses[1].status());
+    assert!(responses[1].headers().contains_key(IDEMPOTENT_REPLAYED));
+    // Another client's request with the same key isn't replayed
+    assert_eq!(StatusCode::OK, responses[2].status());
+    assert!(!responses[2].headers().contains_key(IDEMPOTENT_REPLAYED));
+    let examples = sqlx::query!("SELECT id FROM example")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch examples");
+    assert_eq!(2, examples.len());
+}
+
+#[tokio::test]
+async fn keys_with_invalid_credentials_are_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .header(IDEMPOTENCY_KEY, "invalid")
+        .basic_auth(&test_app.test_user.username, Some("wrong-password"))
+        .form(&BODY)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    let examples = sqlx::query!("SELECT id FROM example")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch examples");
+    assert!(examples.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_keys_are_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .header(IDEMPOTENCY_KEY, "a".repeat(256))
+        .form(&BODY)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
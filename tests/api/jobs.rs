@@ -0,0 +1,160 @@
+use crate::utils::spawn_app_with;
+use actix_web_template::domain::Email;
+use actix_web_template::email::EmailSender;
+use actix_web_template::jobs::{enqueue, Job, JobContext, SendEmail};
+use anyhow::anyhow;
+use chrono::Utc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Records the subject of each email sent.
+#[derive(Clone, Default)]
+struct RecordingEmailSender(Arc<Mutex<Vec<String>>>);
+
+#[async_trait::async_trait]
+impl EmailSender for RecordingEmailSender {
+    async fn send(
+        &self,
+        _recipient: &Email,
+        subject: &str,
+        _body: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.0.lock().unwrap().push(subject.into());
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FailingJob;
+
+#[async_trait::async_trait]
+impl Job for FailingJob {
+    const KIND: &'static str = "failing";
+    const MAX_ATTEMPTS: i32 = 3;
+
+    async fn run(self, _context: &JobContext) -> Result<(), anyhow::Error> {
+        Err(anyhow!("Always fails"))
+    }
+}
+
+#[tokio::test]
+async fn queued_emails_are_sent_by_workers() {
+    let sender = RecordingEmailSender::default();
+    let sent = sender.0.clone();
+    let test_app = spawn_app_with(|builder| builder.email_sender(sender)).await;
+    let job = SendEmail {
+        recipient: "barry@barry.com".into(),
+        subject: "Welcome".into(),
+        body: "Hi Barry".into(),
+    };
+
+    enqueue(&job, Utc::now(), &test_app.db_pool)
+        .await
+        .expect("Failed to enqueue job");
+
+    for _ in 0..50 {
+        if !sent.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(vec!["Welcome".to_string()], *sent.lock().unwrap());
+    // Completed jobs are removed from the queue
+    let remaining = sqlx::query!("SELECT id FROM jobs")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch jobs");
+    assert!(remaining.is_empty());
+}
+
+#[tokio::test]
+async fn failing_jobs_are_retried_then_dead_lettered() {
+    let test_app = spawn_app_with(|builder| builder.job::<FailingJob>()).await;
+
+    let id = enqueue(&FailingJob, Utc::now(), &test_app.db_pool)
+        .await
+        .expect("Failed to enqueue job");
+
+    let mut job = None;
+    for _ in 0..50 {
+        let row = sqlx::query!(
+            "SELECT status, attempts, last_error FROM jobs WHERE id = $1",
+            id
+        )
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch job");
+        if row.status == "dead" {
+            job = Some(row);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let job = job.expect("Job was not dead-lettered");
+    assert_eq!(FailingJob::MAX_ATTEMPTS, job.attempts);
+    assert!(job.last_error.unwrap().contains("Always fails"));
+}
+
+#[tokio::test]
+async fn jobs_are_not_run_before_they_are_due() {
+    let sender = RecordingEmailSender::default();
+    let sent = sender.0.clone();
+    let test_app = spawn_app_with(|builder| builder.email_sender(sender)).await;
+    let job = SendEmail {
+        recipient: "barry@barry.com".into(),
+        subject: "Later".into(),
+        body: "Hi Barry".into(),
+    };
+
+    enqueue(
+        &job,
+        Utc::now() + chrono::Duration::hours(1),
+        &test_app.db_pool,
+    )
+    .await
+    .expect("Failed to enqueue job");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert!(sent.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn jobs_whose_lease_expired_are_run_again() {
+    let sender = RecordingEmailSender::default();
+    let sent = sender.0.clone();
+    let test_app = spawn_app_with(|builder| builder.email_sender(sender)).await;
+    let job = SendEmail {
+        recipient: "barry@barry.com".into(),
+        subject: "Retried".into(),
+        body: "Hi Barry".into(),
+    };
+    let id = enqueue(
+        &job,
+        Utc::now() + chrono::Duration::hours(1),
+        &test_app.db_pool,
+    )
+    .await
+    .expect("Failed to enqueue job");
+
+    // Claimed by a worker which died before finishing it
+    sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = 'running', attempts = 1, run_at = now(),
+            locked_until = now() - interval '1 minute'
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .expect("Failed to claim job");
+
+    for _ in 0..50 {
+        if !sent.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(vec!["Retried".to_string()], *sent.lock().unwrap());
+}
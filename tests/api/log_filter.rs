@@ -0,0 +1,104 @@
+use crate::utils::{spawn_app, TestUser};
+use actix_web_template::endpoint::{get_log_filter, set_log_filter};
+use actix_web_template::telemetry::LogFilterStatus;
+use reqwest::StatusCode;
+
+#[tokio::test]
+async fn log_filter_requires_credentials() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let address = test_app.address;
+
+    let get_response = client
+        .get(format!("{address}{}", get_log_filter::PATH))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let put_response = client
+        .put(format!("{address}{}", set_log_filter::PATH))
+        .basic_auth(test_app.test_user.username, Some("wrong_password"))
+        .json(&serde_json::json!({ "directive": "debug" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, get_response.status());
+    assert_eq!(StatusCode::UNAUTHORIZED, put_response.status());
+}
+
+#[tokio::test]
+async fn log_filter_can_be_changed_temporarily() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let address = test_app.address;
+    let user = &test_app.test_user;
+
+    let put_response = client
+        .put(format!("{address}{}", set_log_filter::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({
+            "directive": "info,actix_web_template=debug",
+            "revert_after_secs": 60,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(StatusCode::OK, put_response.status());
+
+    let status: LogFilterStatus = client
+        .get(format!("{address}{}", get_log_filter::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Failed to parse log filter status");
+
+    assert!(status.directive.contains("actix_web_template=debug"));
+    assert!(status.revert_at.is_some());
+}
+
+#[tokio::test]
+async fn invalid_log_filter_is_rejected() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let address = test_app.address;
+    let user = &test_app.test_user;
+
+    let response = client
+        .put(format!("{address}{}", set_log_filter::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({ "directive": "info,[bad" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[tokio::test]
+async fn log_filter_requires_an_admin() {
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let address = test_app.address;
+    let user = TestUser::generate();
+    user.store(&test_app.db_pool).await;
+
+    let get_response = client
+        .get(format!("{address}{}", get_log_filter::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let put_response = client
+        .put(format!("{address}{}", set_log_filter::PATH))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({ "directive": "trace" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(StatusCode::FORBIDDEN, get_response.status());
+    assert_eq!(StatusCode::FORBIDDEN, put_response.status());
+}
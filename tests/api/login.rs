@@ -1,4 +1,5 @@
 use crate::utils::{assert_is_redirect_to, spawn_app};
+use actix_web_template::endpoint::login_form;
 
 #[tokio::test]
 async fn error_flash_msg_is_set_on_failed_login_attempt() {
@@ -13,7 +14,7 @@ async fn error_flash_msg_is_set_on_failed_login_attempt() {
     });
     let response = app.post_login(&login_body).await;
 
-    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&response, &login_form::url());
 
     // HTML error msg is rendered correctly
     let html_page = app.get_login_html().await;
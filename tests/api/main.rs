@@ -1,7 +1,20 @@
 extern crate core;
 
+mod api_version;
+mod audit;
+mod database;
 mod example_auth;
 mod example_post_and_get;
 mod health_check;
+mod idempotency;
+mod jobs;
+mod log_filter;
 mod login;
+mod metrics;
+mod migrations;
+mod ready;
+mod replicas;
+mod scheduler;
+mod shutdown;
+mod transaction;
 mod utils;
@@ -0,0 +1,42 @@
+use crate::utils::spawn_app;
+use actix_web_template::endpoint::{example_get, health_check, metrics};
+
+#[tokio::test]
+async fn metrics_are_labelled_by_route_template() {
+    let test_app = spawn_app().await;
+    let address = test_app.address;
+    let client = reqwest::Client::new();
+
+    client
+        .get(format!("{address}{}", health_check::url()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    client
+        .get(format!("{address}{}", example_get::url("barry@barry.com")))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let response = client
+        .get(format!("{address}{}", metrics::url()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.expect("Failed to read metrics");
+
+    assert!(body.contains(&format!(
+        r#"http_requests_total{{method="GET",route="{}",status="200"}}"#,
+        health_check::PATH
+    )));
+    assert!(body.contains(&format!(
+        r#"http_requests_total{{method="GET",route="{}",status="404"}}"#,
+        example_get::PATH
+    )));
+    assert!(body.contains("http_request_duration_seconds_bucket"));
+    assert!(
+        body.contains(r#"db_query_duration_seconds_count{query="read_db"}"#)
+    );
+    assert!(body.contains(r#"db_pool_connections{state="idle"}"#));
+}
@@ -0,0 +1,112 @@
+use crate::utils::{create_database, spawn_app_with_settings};
+use actix_web_template::configuration::Settings;
+use actix_web_template::migrations::{self, MigrationState};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn empty_database() -> PgPool {
+    let mut configuration =
+        Settings::get_config().expect("Failed to load configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    create_database(&configuration.database).await
+}
+
+async fn states(pool: &PgPool) -> Vec<MigrationState> {
+    migrations::status(pool)
+        .await
+        .expect("Failed to get migration status")
+        .migrations
+        .into_iter()
+        .map(|status| status.state)
+        .collect()
+}
+
+#[tokio::test]
+async fn concurrent_runs_apply_each_migration_once() {
+    let pool = empty_database().await;
+    assert!(states(&pool)
+        .await
+        .iter()
+        .all(|state| *state == MigrationState::Pending));
+
+    let (first, second) =
+        tokio::join!(migrations::run(&pool), migrations::run(&pool));
+
+    first.expect("First run failed");
+    second.expect("Second run failed");
+    assert!(states(&pool)
+        .await
+        .iter()
+        .all(|state| *state == MigrationState::Applied));
+}
+
+#[tokio::test]
+async fn status_lists_pending_and_changed_migrations() {
+    let pool = empty_database().await;
+    migrations::run(&pool)
+        .await
+        .expect("Failed to run migrations");
+    let versions: Vec<i64> =
+        migrations::MIGRATOR.iter().map(|m| m.version).collect();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+        .bind(versions[versions.len() - 1])
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE _sqlx_migrations SET checksum = '' WHERE version = $1")
+        .bind(versions[0])
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let states = states(&pool).await;
+
+    assert_eq!(MigrationState::ChecksumMismatch, states[0]);
+    assert_eq!(MigrationState::Applied, states[1]);
+    assert_eq!(MigrationState::Pending, states[states.len() - 1]);
+}
+
+#[tokio::test]
+async fn status_of_a_new_database_creates_no_migrations_table() {
+    let pool = empty_database().await;
+
+    let report = migrations::status(&pool)
+        .await
+        .expect("Failed to get migration status");
+
+    assert!(!report.has_migrations_table);
+    assert!(report
+        .migrations
+        .iter()
+        .all(|status| status.state == MigrationState::Pending));
+    assert!(report.to_string().starts_with("No migrations table"));
+    assert!(
+        !migrations::status(&pool)
+            .await
+            .unwrap()
+            .has_migrations_table
+    );
+}
+
+#[tokio::test]
+async fn apps_migrating_on_startup_serve_a_new_database() {
+    let test_app = spawn_app_with_settings(
+        |settings| settings.database.migrate_on_startup = true,
+        |builder| builder,
+    )
+    .await;
+
+    // The test user is stored in the migrated database
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": test_app.test_user.username,
+            "password": test_app.test_user.password,
+        }))
+        .await;
+
+    assert_eq!(303, response.status().as_u16());
+    assert!(states(&test_app.db_pool)
+        .await
+        .iter()
+        .all(|state| *state == MigrationState::Applied));
+}
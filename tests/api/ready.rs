@@ -0,0 +1,57 @@
+use crate::utils::spawn_app;
+use actix_web_template::endpoint::ready;
+use actix_web_template::readiness::{CheckStatus, ReadinessReport};
+
+#[tokio::test]
+async fn ready_returns_200_when_dependencies_are_available() {
+    let test_app = spawn_app().await;
+    let address = test_app.address;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{address}{}", ready::url()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    let report: ReadinessReport = response
+        .json()
+        .await
+        .expect("Failed to parse readiness report");
+    assert!(report.ready);
+    for check in ["postgres", "migrations"] {
+        let check = report
+            .checks
+            .iter()
+            .find(|c| c.name == check)
+            .expect("Check missing from readiness report");
+        assert_eq!(CheckStatus::Ok, check.status);
+    }
+}
+
+#[tokio::test]
+async fn ready_returns_503_when_migrations_are_pending() {
+    let test_app = spawn_app().await;
+    let address = test_app.address;
+    let client = reqwest::Client::new();
+
+    sqlx::query("DELETE FROM _sqlx_migrations")
+        .execute(&test_app.db_pool)
+        .await
+        .expect("Failed to remove migration history");
+
+    let response = client
+        .get(format!("{address}{}", ready::url()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(503, response.status().as_u16());
+    let body = response.text().await.expect("Failed to read response");
+    // Failures are only detailed in the logs
+    assert!(!body.contains("Pending migrations"), "{body}");
+    let report: ReadinessReport =
+        serde_json::from_str(&body).expect("Failed to parse readiness report");
+    assert!(!report.ready);
+}
@@ -0,0 +1,119 @@
+use crate::utils::{
+    configure_database, spawn_app_with, spawn_app_with_settings,
+};
+use actix_web_template::configuration::Settings;
+use actix_web_template::endpoint::{example_get, example_post};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A separate database standing in for a replica, so reads from it can be
+/// told apart from reads from the primary.
+async fn replica_database() -> sqlx::PgPool {
+    let mut settings =
+        Settings::get_config().expect("Failed to load configuration");
+    settings.database.database_name = Uuid::new_v4().to_string();
+    configure_database(&settings.database).await
+}
+
+#[tokio::test]
+async fn reads_go_to_the_replica_until_the_session_writes() {
+    let replica = replica_database().await;
+    sqlx::query(
+        "INSERT INTO example (id, email, name, added_at)
+        VALUES ($1, 'replica@example.com', 'replica', now())",
+    )
+    .bind(Uuid::new_v4())
+    .execute(&replica)
+    .await
+    .expect("Failed to insert into replica");
+    let test_app =
+        spawn_app_with(|builder| builder.replica_pools(vec![replica])).await;
+    let get = |client: &reqwest::Client, email: &str| {
+        client
+            .get(format!("{}{}", test_app.address, example_get::url(email)))
+            .send()
+    };
+
+    // Replicas are read from once they've passed a health check
+    let mut status = 0;
+    for _ in 0..20 {
+        status = get(&test_app.api_client, "replica@example.com")
+            .await
+            .expect("GET request failed")
+            .status()
+            .as_u16();
+        if status == 200 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(200, status);
+
+    let post_response = test_app
+        .api_client
+        .post(format!("{}{}", test_app.address, example_post::PATH))
+        .form(&[("name", "primary"), ("email", "primary@example.com")])
+        .send()
+        .await
+        .expect("POST request failed");
+    assert_eq!(200, post_response.status().as_u16());
+
+    // The writing session reads from the primary, others from the replica
+    let writer = get(&test_app.api_client, "primary@example.com")
+        .await
+        .expect("GET request failed");
+    let other = get(&reqwest::Client::new(), "primary@example.com")
+        .await
+        .expect("GET request failed");
+    assert_eq!(200, writer.status().as_u16());
+    assert_eq!(404, other.status().as_u16());
+}
+
+#[tokio::test]
+async fn reads_fail_over_to_the_primary_when_a_replica_stops() {
+    let replica = replica_database().await;
+    sqlx::query(
+        "INSERT INTO example (id, email, name, added_at)
+        VALUES ($1, 'replica@example.com', 'replica', now())",
+    )
+    .bind(Uuid::new_v4())
+    .execute(&replica)
+    .await
+    .expect("Failed to insert into replica");
+    // Only the first health check runs during the test
+    let test_app = spawn_app_with_settings(
+        |settings| {
+            settings.database.replication.health_check_interval_secs = 3600
+        },
+        |builder| builder.replica_pools(vec![replica.clone()]),
+    )
+    .await;
+    let get = |email: &str| {
+        reqwest::Client::new()
+            .get(format!("{}{}", test_app.address, example_get::url(email)))
+            .send()
+    };
+    let mut status = 0;
+    for _ in 0..20 {
+        status = get("replica@example.com")
+            .await
+            .expect("GET request failed")
+            .status()
+            .as_u16();
+        if status == 200 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(200, status);
+
+    // Stopped while it's still considered healthy
+    replica.close().await;
+
+    // Read from the primary, which doesn't have the replica's entry, rather
+    // than failing
+    let response = get("replica@example.com")
+        .await
+        .expect("GET request failed");
+    assert_eq!(404, response.status().as_u16());
+}
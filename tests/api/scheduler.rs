@@ -0,0 +1,126 @@
+use crate::utils::spawn_app;
+use actix_web_template::clock::SystemClock;
+use actix_web_template::configuration::CronSchedule;
+use actix_web_template::email::LogEmailSender;
+use actix_web_template::jobs::JobContext;
+use actix_web_template::scheduler::{
+    run_scheduled_task, PurgeExamples, ScheduledTask, Scheduler,
+};
+use actix_web_template::shutdown::ShutdownHook;
+use chrono::{TimeZone, Utc};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Counts its runs, each taking a little while.
+#[derive(Default)]
+struct CountingTask(AtomicUsize);
+
+#[async_trait::async_trait]
+impl ScheduledTask for CountingTask {
+    fn name(&self) -> &'static str {
+        "counting"
+    }
+
+    async fn run(&self, _context: &JobContext) -> Result<(), anyhow::Error> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        Ok(())
+    }
+}
+
+fn context(db_pool: &PgPool) -> JobContext {
+    JobContext {
+        db_pool: db_pool.clone(),
+        clock: Arc::new(SystemClock),
+        email_sender: Arc::new(LogEmailSender),
+    }
+}
+
+#[tokio::test]
+async fn scheduled_tasks_run_on_their_schedule() {
+    let test_app = spawn_app().await;
+    let task = Arc::new(CountingTask::default());
+    let schedules = BTreeMap::from([(
+        "counting".to_string(),
+        CronSchedule::try_from("* * * * * *".to_string()).unwrap(),
+    )]);
+    let scheduler = Scheduler::new(
+        schedules,
+        context(&test_app.db_pool),
+        vec![task.clone()],
+    );
+
+    scheduler.spawn();
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    scheduler
+        .shutdown()
+        .await
+        .expect("Failed to stop scheduler");
+
+    assert!(task.0.load(Ordering::SeqCst) >= 1);
+    let run = sqlx::query!(
+        "SELECT last_error FROM scheduled_task_runs WHERE name = 'counting'"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Run was not recorded");
+    assert_eq!(None, run.last_error);
+}
+
+#[tokio::test]
+async fn each_scheduled_run_is_only_made_by_one_replica() {
+    let test_app = spawn_app().await;
+    let task = CountingTask::default();
+    let context = context(&test_app.db_pool);
+    // Scheduled runs are on whole seconds
+    let scheduled_at = Utc.with_ymd_and_hms(2026, 10, 19, 3, 0, 0).unwrap();
+
+    // Both replicas wake for the same run at once
+    let (first, second) = tokio::join!(
+        run_scheduled_task(&task, scheduled_at, &context),
+        run_scheduled_task(&task, scheduled_at, &context),
+    );
+    // A replica waking late doesn't repeat the run
+    let late = run_scheduled_task(&task, scheduled_at, &context).await;
+
+    assert!(first.unwrap() ^ second.unwrap());
+    assert!(!late.unwrap());
+    assert_eq!(1, task.0.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn purge_examples_deletes_old_examples() {
+    let test_app = spawn_app().await;
+    for (email, age) in [("old@barry.com", 31), ("new@barry.com", 1)] {
+        sqlx::query!(
+            r#"
+            INSERT INTO example (id, email, name, added_at)
+            VALUES ($1, $2, 'Barry', $3)
+            "#,
+            Uuid::new_v4(),
+            email,
+            Utc::now() - chrono::Duration::days(age),
+        )
+        .execute(&test_app.db_pool)
+        .await
+        .expect("Failed to insert example");
+    }
+    let task = PurgeExamples {
+        max_age: chrono::Duration::days(30),
+    };
+
+    task.run(&context(&test_app.db_pool))
+        .await
+        .expect("Failed to purge examples");
+
+    let remaining = sqlx::query!("SELECT email FROM example")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch examples");
+    assert_eq!(1, remaining.len());
+    assert_eq!("new@barry.com", remaining[0].email);
+}
@@ -0,0 +1,43 @@
+use crate::utils::spawn_app;
+use actix_web_template::endpoint::{health_check, ready};
+use actix_web_template::readiness::{CheckStatus, ReadinessReport};
+use std::time::Duration;
+
+#[tokio::test]
+async fn shutdown_fails_readiness_before_stopping_server() {
+    let test_app = spawn_app().await;
+    let address = &test_app.address;
+    let client = reqwest::Client::new();
+
+    test_app.shutdown.trigger();
+    // Give the shutdown task a chance to start draining
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Still serving requests during the drain delay, but not ready
+    let response = client
+        .get(format!("{address}{}", ready::url()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(503, response.status().as_u16());
+    let report: ReadinessReport = response
+        .json()
+        .await
+        .expect("Failed to parse readiness report");
+    let check = report
+        .checks
+        .iter()
+        .find(|check| check.name == "shutdown")
+        .expect("Check missing from readiness report");
+    assert_eq!(CheckStatus::Failed, check.status);
+
+    // Stopped after the drain delay and the timeout for the open keep-alive
+    // connection, with the pool closed by its hook
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let response = reqwest::Client::new()
+        .get(format!("{address}{}", health_check::url()))
+        .send()
+        .await;
+    assert!(response.is_err(), "Server is still accepting requests");
+    assert!(test_app.db_pool.is_closed());
+}
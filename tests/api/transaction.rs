@@ -0,0 +1,200 @@
+use crate::utils::spawn_app;
+use actix_web::body::MessageBody;
+use actix_web::dev::{
+    Service, ServiceFactory, ServiceRequest, ServiceResponse,
+};
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{web, App, HttpResponse};
+use actix_web_template::configuration::{IsolationLevel, TransactionSettings};
+use actix_web_template::database::{Transactional, UnitOfWork};
+use sqlx::{Executor, PgPool};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(serde::Deserialize)]
+struct Entry {
+    name: String,
+    /// Responds with this status after inserting the entry
+    status: u16,
+    /// Attempts failing with a serialization failure before the insert
+    failures: u32,
+    /// Fail with a deadlock instead
+    #[serde(default)]
+    deadlock: bool,
+}
+
+async fn insert_entry(
+    form: web::Form<Entry>,
+    attempts: web::Data<AtomicU32>,
+    unit_of_work: UnitOfWork,
+) -> HttpResponse {
+    let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+    let mut transaction = unit_of_work.transaction().await;
+    let query = match attempt < form.failures {
+        true if form.deadlock => sqlx::query(
+            "DO $$ BEGIN
+                RAISE EXCEPTION USING ERRCODE = 'deadlock_detected';
+            END $$",
+        ),
+        true => sqlx::query(
+            "DO $$ BEGIN
+                RAISE EXCEPTION USING ERRCODE = 'serialization_failure';
+            END $$",
+        ),
+        false => {
+            sqlx::query("INSERT INTO entry (name) VALUES ($1)").bind(&form.name)
+        }
+    };
+    match unit_of_work.check(query.execute(&mut *transaction).await) {
+        Ok(_) => {
+            HttpResponse::build(StatusCode::from_u16(form.status).unwrap())
+                .finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn create_table(pool: &PgPool) {
+    pool.execute("CREATE TABLE entry (name TEXT NOT NULL)")
+        .await
+        .expect("Failed to create table");
+}
+
+fn app(
+    pool: &PgPool,
+    attempts: web::Data<AtomicU32>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .wrap(
+            Transactional::new(
+                pool.clone(),
+                TransactionSettings {
+                    isolation_level: IsolationLevel::Serializable,
+                    max_retries: 2,
+                },
+            )
+            .routes(|_, route| route == "/"),
+        )
+        .app_data(attempts)
+        .route("/", web::post().to(insert_entry))
+        .route("/untracked", web::post().to(insert_entry))
+}
+
+async fn entries(pool: &PgPool) -> Vec<String> {
+    sqlx::query_scalar("SELECT name FROM entry")
+        .fetch_all(pool)
+        .await
+        .expect("Failed to fetch entries")
+}
+
+fn request(status: u16, failures: u32) -> TestRequest {
+    TestRequest::post().uri("/").set_form([
+        ("name", "barry".to_string()),
+        ("status", status.to_string()),
+        ("failures", failures.to_string()),
+    ])
+}
+
+#[actix_web::test]
+async fn successful_responses_commit_the_transaction() {
+    let test_app = spawn_app().await;
+    let attempts = web::Data::new(AtomicU32::new(0));
+    create_table(&test_app.db_pool).await;
+    let app = init_service(app(&test_app.db_pool, attempts)).await;
+
+    let response = call_service(&app, request(201, 0).to_request()).await;
+
+    assert_eq!(StatusCode::CREATED, response.status());
+    assert_eq!(vec!["barry"], entries(&test_app.db_pool).await);
+}
+
+#[actix_web::test]
+async fn error_responses_roll_back_the_transaction() {
+    let test_app = spawn_app().await;
+    let attempts = web::Data::new(AtomicU32::new(0));
+    create_table(&test_app.db_pool).await;
+    let app = init_service(app(&test_app.db_pool, attempts)).await;
+
+    let response = call_service(&app, request(400, 0).to_request()).await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert!(entries(&test_app.db_pool).await.is_empty());
+}
+
+#[actix_web::test]
+async fn serialization_failures_retry_the_request() {
+    let test_app = spawn_app().await;
+    let attempts = web::Data::new(AtomicU32::new(0));
+    create_table(&test_app.db_pool).await;
+    let app = init_service(app(&test_app.db_pool, attempts.clone())).await;
+
+    // The retries are sent the original body
+    let response = call_service(&app, request(200, 2).to_request()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(3, attempts.load(Ordering::SeqCst));
+    assert_eq!(vec!["barry"], entries(&test_app.db_pool).await);
+}
+
+#[actix_web::test]
+async fn requests_out_of_retries_conflict() {
+    let test_app = spawn_app().await;
+    let attempts = web::Data::new(AtomicU32::new(0));
+    create_table(&test_app.db_pool).await;
+    let app = init_service(app(&test_app.db_pool, attempts.clone())).await;
+
+    let response = app.call(request(200, 3).to_request()).await;
+
+    let error = response.err().expect("Request should fail");
+    assert_eq!(
+        StatusCode::CONFLICT,
+        error.as_response_error().status_code()
+    );
+    assert_eq!(3, attempts.load(Ordering::SeqCst));
+    assert!(entries(&test_app.db_pool).await.is_empty());
+}
+
+#[actix_web::test]
+async fn deadlocks_retry_the_request() {
+    let test_app = spawn_app().await;
+    let attempts = web::Data::new(AtomicU32::new(0));
+    create_table(&test_app.db_pool).await;
+    let app = init_service(app(&test_app.db_pool, attempts.clone())).await;
+
+    let request = TestRequest::post().uri("/").set_form([
+        ("name", "barry"),
+        ("status", "200"),
+        ("failures", "1"),
+        ("deadlock", "true"),
+    ]);
+
+    let response = call_service(&app, request.to_request()).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(2, attempts.load(Ordering::SeqCst));
+    assert_eq!(vec!["barry"], entries(&test_app.db_pool).await);
+}
+
+#[actix_web::test]
+async fn other_routes_have_no_unit_of_work() {
+    let test_app = spawn_app().await;
+    let attempts = web::Data::new(AtomicU32::new(0));
+    create_table(&test_app.db_pool).await;
+    let app = init_service(app(&test_app.db_pool, attempts.clone())).await;
+
+    let response =
+        call_service(&app, request(200, 0).uri("/untracked").to_request())
+            .await;
+
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+    assert_eq!(0, attempts.load(Ordering::SeqCst));
+    assert!(entries(&test_app.db_pool).await.is_empty());
+}
@@ -1,69 +1,134 @@
+use actix_web_template::audit::AuditEvent;
 use actix_web_template::auth::compute_password_hash;
+use actix_web_template::clock::Clock;
 use actix_web_template::configuration::{
-    DatabaseSettings, HmacSecret, Settings,
+    DatabaseSettings, JobsSettings, LogFormat, LoggingSettings, Settings,
+    ShutdownSettings, TelemetrySettings,
 };
-use actix_web_template::startup::run;
-use actix_web_template::telemetry::{get_subscriber, init_subscriber};
+use actix_web_template::endpoint::{audit_events, login, login_form};
+use actix_web_template::migrations::{self, MigrationState};
+use actix_web_template::shutdown::Shutdown;
+use actix_web_template::startup::{Application, ApplicationBuilder};
+use actix_web_template::telemetry::{
+    get_subscriber, init_subscriber, init_tracer_provider, LogFilter,
+};
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
+use opentelemetry::trace::TracerProvider;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use std::net::TcpListener;
+use std::time::Duration;
 use uuid::Uuid;
 
 const TEST_HOST: &str = "127.0.0.1";
 // Port 0 selects a random available port
 const TEST_PORT: u16 = 0;
 
-static TRACING: Lazy<()> = Lazy::new(|| {
-    let default_filter_level = "info".to_string();
+/// Every test app shares the global subscriber, and so its log filter
+static TRACING: Lazy<LogFilter> = Lazy::new(|| {
     let subscriber_name = "test".to_string();
-    if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(
-            subscriber_name,
-            default_filter_level,
-            std::io::stdout,
-        );
+    // Spans get trace ids, but aren't exported anywhere
+    let tracer = init_tracer_provider(
+        subscriber_name.clone(),
+        &TelemetrySettings::default(),
+    )
+    .expect("Failed to initialise tracer provider")
+    .tracer(subscriber_name.clone());
+    if let Ok(test_log) = std::env::var("TEST_LOG") {
+        let settings = LoggingSettings {
+            format: LogFormat::try_from(test_log).unwrap_or_default(),
+            ..Default::default()
+        };
+        let (subscriber, log_filter) =
+            get_subscriber(subscriber_name, &settings, std::io::stdout, tracer);
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber(
+        let (subscriber, log_filter) = get_subscriber(
             subscriber_name,
-            default_filter_level,
+            &LoggingSettings::default(),
             std::io::sink,
+            tracer,
         );
         init_subscriber(subscriber);
-    };
+        log_filter
+    }
 });
 
+/// Always tells the same time.
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
 /// Spawn an instance of the app using a random available port and return the
 /// address used, including the selected port.
 ///
 /// Use `TEST_LOG=true` to view all log outputs from tests.
 /// For example: `TEST_LOG=true cargo test` or `TEST_LOG=true cargo test | bunyan`
 /// if you'd like to prettify log output through the bunyan cli app.
+/// `TEST_LOG` can also name a log format, e.g. `TEST_LOG=pretty cargo test`.
 pub async fn spawn_app() -> TestApp {
-    Lazy::force(&TRACING);
+    spawn_app_with(|builder| builder).await
+}
+
+/// Spawn an instance of the app, with components overridden by `customise`.
+pub async fn spawn_app_with(
+    customise: impl FnOnce(ApplicationBuilder) -> ApplicationBuilder,
+) -> TestApp {
+    spawn_app_with_settings(|_| {}, customise).await
+}
 
-    let test_address = format!("{TEST_HOST}:{TEST_PORT}");
-    let listener =
-        TcpListener::bind(test_address).expect("Failed to bind to random port");
-    let actual_port = listener.local_addr().unwrap().port();
-    let address = format!("http://{TEST_HOST}:{actual_port}");
+/// Spawn an instance of the app, with settings changed by `configure` before
+/// components are overridden by `customise`.
+pub async fn spawn_app_with_settings(
+    configure: impl FnOnce(&mut Settings),
+    customise: impl FnOnce(ApplicationBuilder) -> ApplicationBuilder,
+) -> TestApp {
+    let log_filter = Lazy::force(&TRACING).clone();
 
     let mut configuration =
         Settings::get_config().expect("Failed to load configuration");
+    configuration.app.host = TEST_HOST.into();
+    configuration.app.port = TEST_PORT;
+    configuration.shutdown = ShutdownSettings {
+        drain_delay_secs: 1,
+        timeout_secs: 1,
+    };
+    // Jobs run promptly, and failed jobs are retried straight away
+    configuration.jobs = JobsSettings {
+        workers: 2,
+        poll_interval_ms: 50,
+        retry_backoff_secs: 0,
+        lease_secs: 60,
+    };
+    configure(&mut configuration);
 
     // Randomise database name so new database is used at start of each test
     configuration.database.database_name = Uuid::new_v4().to_string();
-    // Create new database with randomised name
-    let db_pool = configure_database(&configuration.database).await;
+    // Create new database with randomised name, left for the app to migrate
+    // if it migrates on startup
+    let migrate_on_startup = configuration.database.migrate_on_startup;
+    let db_pool = match migrate_on_startup {
+        true => create_database(&configuration.database).await,
+        false => configure_database(&configuration.database).await,
+    };
 
-    let server = run(
-        listener,
-        db_pool.clone(),
-        HmacSecret(configuration.app.hmac_secret),
-    )
-    .expect("Failed to bind address");
-    let _ = tokio::spawn(server);
+    let builder = Application::builder(configuration, log_filter)
+        .db_pool(db_pool.clone())
+        .without_signals();
+    let application = customise(builder)
+        .build()
+        .expect("Failed to build application");
+    let address = format!("http://{TEST_HOST}:{}", application.port());
+    let shutdown = application.shutdown().clone();
+    tokio::spawn(application.run_until_stopped());
+    if migrate_on_startup {
+        wait_for_migrations(&db_pool).await;
+    }
 
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -74,8 +139,9 @@ pub async fn spawn_app() -> TestApp {
     let test_app = TestApp {
         address,
         db_pool,
-        test_user: TestUser::generate(),
+        test_user: TestUser::generate_admin(),
         api_client,
+        shutdown,
     };
     test_app.test_user.store(&test_app.db_pool).await;
 
@@ -95,6 +161,7 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub shutdown: Shutdown,
 }
 
 impl TestApp {
@@ -103,16 +170,32 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}{}", &self.address, login::url()))
             .form(body)
             .send()
             .await
             .expect("Failed to execute login request")
     }
 
+    /// Audit events matching the `query` string, newest first
+    pub async fn get_audit_events(&self, query: &str) -> Vec<AuditEvent> {
+        self.api_client
+            .get(format!("{}{}{query}", &self.address, audit_events::PATH))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .send()
+            .await
+            .expect("Failed to request audit events")
+            .json()
+            .await
+            .expect("Failed to parse audit events")
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}{}", &self.address, login_form::url()))
             .send()
             .await
             .expect("Failed to request login form")
@@ -125,6 +208,7 @@ impl TestApp {
 pub struct TestUser {
     pub username: String,
     pub password: String,
+    pub admin: bool,
 }
 
 impl TestUser {
@@ -132,6 +216,15 @@ impl TestUser {
         Self {
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            admin: false,
+        }
+    }
+
+    /// A user who can use the admin endpoints, as the app's `test_user` is
+    pub fn generate_admin() -> Self {
+        Self {
+            admin: true,
+            ..Self::generate()
         }
     }
 
@@ -145,10 +238,11 @@ impl TestUser {
         .unwrap();
 
         sqlx::query!(
-            "INSERT INTO users (username, password)\
-            VALUES ($1, $2)",
+            "INSERT INTO users (username, password, admin)\
+            VALUES ($1, $2, $3)",
             self.username,
-            password_hash.expose_secret()
+            password_hash.expose_secret(),
+            self.admin
         )
         .execute(pool)
         .await
@@ -156,7 +250,38 @@ impl TestUser {
     }
 }
 
-async fn configure_database(db_config: &DatabaseSettings) -> PgPool {
+/// Create a database named in `db_config`, and apply the migrations.
+/// Wait for the app to apply every migration, or panic after 10 seconds.
+async fn wait_for_migrations(db_pool: &PgPool) {
+    for _ in 0..100 {
+        let report = migrations::status(db_pool)
+            .await
+            .expect("Failed to get migration status");
+        if report
+            .migrations
+            .iter()
+            .all(|status| status.state == MigrationState::Applied)
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The app didn't apply the migrations on startup");
+}
+
+pub async fn configure_database(db_config: &DatabaseSettings) -> PgPool {
+    let db_pool = create_database(db_config).await;
+
+    sqlx::migrate!("./migrations")
+        .run(&db_pool)
+        .await
+        .expect("Failed to migrate newly created database");
+
+    db_pool
+}
+
+/// Create an empty database named in `db_config`, without migrating it.
+pub async fn create_database(db_config: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&db_config.without_db())
         .await
         .expect("Failed to connect to Postgres");
@@ -169,14 +294,7 @@ async fn configure_database(db_config: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to create database");
 
-    let db_pool = PgPool::connect_with(db_config.with_db())
-        .await
-        .expect("Failed to connect to newly created database.");
-
-    sqlx::migrate!("./migrations")
-        .run(&db_pool)
+    PgPool::connect_with(db_config.with_db())
         .await
-        .expect("Failed to migrate newly created database");
-
-    db_pool
+        .expect("Failed to connect to newly created database.")
 }
@@ -0,0 +1,277 @@
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{web, App, HttpResponse};
+use actix_web_template::configuration::{
+    LogDestination, LogFormat, LogRotation, LoggingSettings, TelemetrySettings,
+};
+use actix_web_template::init_sensitive_request_trace;
+use actix_web_template::telemetry::{
+    get_subscriber, hash_sensitive, init_tracer_provider, make_writer,
+};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{SdkTracerProvider, SpanData, SpanExporter};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_actix_web::TracingLogger;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Stand-in for an OTLP collector, sending the request line of each export
+/// request it receives.
+fn spawn_collector() -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .expect("Failed to bind collector to random port");
+    let endpoint =
+        format!("http://{}/v1/traces", listener.local_addr().unwrap());
+    let (sender, receiver) = channel();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.expect("Failed to accept connection");
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            let _ = sender.send(request_line.trim().to_string());
+        }
+    });
+
+    (endpoint, receiver)
+}
+
+/// Keeps exported spans in memory, so their attributes can be read.
+#[derive(Clone, Debug, Default)]
+struct CapturedSpans(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for CapturedSpans {
+    fn export(
+        &self,
+        batch: Vec<SpanData>,
+    ) -> impl Future<Output = OTelSdkResult> + Send {
+        self.0.lock().unwrap().extend(batch);
+        std::future::ready(Ok(()))
+    }
+}
+
+/// Collects everything written by the subscriber.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn spans_are_exported_to_otlp_collector() {
+    let (endpoint, receiver) = spawn_collector();
+    let settings = TelemetrySettings {
+        otlp_endpoint: Some(endpoint),
+    };
+    let provider = init_tracer_provider("test".into(), &settings)
+        .expect("Failed to initialise tracer provider");
+    let (subscriber, _) = get_subscriber(
+        "test".into(),
+        &LoggingSettings::default(),
+        std::io::sink,
+        provider.tracer("test"),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("Exported span").in_scope(|| {
+            tracing::info!("Inside exported span");
+        });
+    });
+    provider.force_flush().expect("Failed to flush spans");
+
+    let request_line = receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Collector did not receive any spans");
+    assert_eq!("POST /v1/traces HTTP/1.1", request_line);
+    provider
+        .shutdown()
+        .expect("Failed to shut down tracer provider");
+}
+
+#[actix_web::test]
+async fn incoming_traceparent_is_logged_as_trace_id() {
+    let provider =
+        init_tracer_provider("test".into(), &TelemetrySettings::default())
+            .expect("Failed to initialise tracer provider");
+    let logs = CapturedLogs::default();
+    let sink = logs.clone();
+    let (subscriber, _) = get_subscriber(
+        "test".into(),
+        &LoggingSettings::default(),
+        move || sink.clone(),
+        provider.tracer("test"),
+    );
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let app = init_service(App::new().wrap(TracingLogger::default()).route(
+        "/",
+        web::get().to(|| async {
+            tracing::info!("Handling request");
+            HttpResponse::Ok().finish()
+        }),
+    ))
+    .await;
+    let request = TestRequest::get()
+        .uri("/")
+        .insert_header((
+            "traceparent",
+            format!("00-{TRACE_ID}-00f067aa0ba902b7-01"),
+        ))
+        .to_request();
+    call_service(&app, request).await;
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let handler_log = logs
+        .lines()
+        .find(|line| line.contains("Handling request"))
+        .expect("Handler log is missing");
+    assert!(
+        handler_log.contains(&format!(r#""trace_id":"{TRACE_ID}""#)),
+        "trace_id missing from {handler_log}"
+    );
+}
+
+#[test]
+fn logs_are_written_to_rolling_file() {
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    let settings = LoggingSettings {
+        format: LogFormat::Compact,
+        destination: LogDestination::File {
+            directory: directory.clone(),
+            prefix: "test.log".into(),
+            rotation: LogRotation::Never,
+        },
+        ..Default::default()
+    };
+    let provider =
+        init_tracer_provider("test".into(), &TelemetrySettings::default())
+            .expect("Failed to initialise tracer provider");
+    let writer =
+        make_writer(&settings.destination).expect("Failed to open log file");
+    let (subscriber, _) = get_subscriber(
+        "test".into(),
+        &settings,
+        writer,
+        provider.tracer("test"),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!("Written to file");
+    });
+
+    let logs = std::fs::read_to_string(directory.join("test.log"))
+        .expect("Log file is missing");
+    assert!(logs.contains("Written to file"), "Unexpected logs: {logs}");
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn sensitive_values_are_not_logged() {
+    let provider =
+        init_tracer_provider("test".into(), &TelemetrySettings::default())
+            .expect("Failed to initialise tracer provider");
+    let logs = CapturedLogs::default();
+    let sink = logs.clone();
+    let (subscriber, _) = get_subscriber(
+        "test".into(),
+        &LoggingSettings::default(),
+        move || sink.clone(),
+        provider.tracer("test"),
+    );
+    let username = "barry";
+    let password = "hunter2";
+
+    tracing::subscriber::with_default(subscriber, || {
+        init_sensitive_request_trace!("Login", [username = username]);
+        tracing::info!(%password, "Contact barry@barry.com");
+    });
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(!logs.contains(r#""username":"barry""#), "{logs}");
+    assert!(logs.contains(&hash_sensitive(username)), "{logs}");
+    assert!(!logs.contains(password), "{logs}");
+    assert!(!logs.contains("barry@barry.com"), "{logs}");
+}
+
+#[test]
+fn sensitive_values_are_not_exported() {
+    let spans = CapturedSpans::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(spans.clone())
+        .build();
+    let (subscriber, _) = get_subscriber(
+        "test".into(),
+        &LoggingSettings::default(),
+        std::io::sink,
+        provider.tracer("test"),
+    );
+    let email = "barry@barry.com";
+    let password = "hunter2";
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("Login", %email, form.password = password)
+            .in_scope(|| {
+                tracing::info!(token = 42, "Contact {email}");
+            });
+    });
+
+    let spans = spans.0.lock().unwrap();
+    let span = spans
+        .iter()
+        .find(|span| span.name == "Login")
+        .expect("Span wasn't exported");
+    let value = |attributes: &[KeyValue], key: &str| {
+        attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.to_string())
+    };
+    assert_eq!(
+        Some("[REDACTED]"),
+        value(&span.attributes, "email").as_deref()
+    );
+    assert_eq!(
+        Some("[REDACTED]"),
+        value(&span.attributes, "form.password").as_deref()
+    );
+    let event = span.events.first().expect("Event wasn't exported");
+    assert_eq!("Contact [REDACTED]", event.name);
+    assert_eq!(
+        Some("[REDACTED]"),
+        value(&event.attributes, "token").as_deref()
+    );
+}